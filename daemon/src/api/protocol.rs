@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Method {
     GET,
@@ -52,7 +53,7 @@ pub async fn parse_request<S: TransportStream>(stream: &mut S) -> anyhow::Result
         return Err(anyhow!("Empty request"));
     }
 
-    let parts: Vec<&str> = line.trim().split_whitespace().collect();
+    let parts: Vec<&str> = line.split_whitespace().collect();
     if parts.len() < 2 {
         return Err(anyhow!("Invalid request line: {:?}", line));
     }
@@ -75,10 +76,10 @@ pub async fn parse_request<S: TransportStream>(stream: &mut S) -> anyhow::Result
             break; // End of headers
         }
 
-        if let Some((key, value)) = trimmed.split_once(':') {
-            if key.trim().eq_ignore_ascii_case("Content-Length") {
-                content_length = value.trim().parse().unwrap_or(0);
-            }
+        if let Some((key, value)) = trimmed.split_once(':')
+            && key.trim().eq_ignore_ascii_case("Content-Length")
+        {
+            content_length = value.trim().parse().unwrap_or(0);
         }
     }
 
@@ -1,9 +1,18 @@
 use crate::api::protocol::{parse_request, write_response, Method, Request, Response};
 use crate::api::transport::{TransportListener, TransportStream};
+use crate::control::Command;
+use crate::event::Event;
+use crate::fsm::Published;
 use log::{error, info};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{oneshot, watch};
 use tokio::task;
 
-pub async fn run_server<L>(mut listener: L) -> anyhow::Result<()>
+pub async fn run_server<L>(
+    mut listener: L,
+    commands: UnboundedSender<Command>,
+    published: watch::Receiver<Published>,
+) -> anyhow::Result<()>
 where
     L: TransportListener + Send + Sync + 'static,
     L::Stream: Send,
@@ -11,8 +20,10 @@ where
     loop {
         match listener.accept().await {
             Ok(stream) => {
+                let commands = commands.clone();
+                let published = published.clone();
                 task::spawn(async move {
-                    if let Err(e) = handle_connection(stream).await {
+                    if let Err(e) = handle_connection(stream, commands, published).await {
                         error!("Connection error: {:?}", e);
                     }
                 });
@@ -25,11 +36,15 @@ where
     }
 }
 
-async fn handle_connection<S: TransportStream>(mut stream: S) -> anyhow::Result<()> {
+async fn handle_connection<S: TransportStream>(
+    mut stream: S,
+    commands: UnboundedSender<Command>,
+    published: watch::Receiver<Published>,
+) -> anyhow::Result<()> {
     match parse_request(&mut stream).await {
         Ok(req) => {
             info!("Received request: {:?} {}", req.method, req.path);
-            let response = handle_request(req);
+            let response = handle_request(req, &commands, &published).await;
             write_response(&mut stream, response).await?;
         }
         Err(e) => {
@@ -41,10 +56,71 @@ async fn handle_connection<S: TransportStream>(mut stream: S) -> anyhow::Result<
     Ok(())
 }
 
-fn handle_request(req: Request) -> Response {
+async fn handle_request(
+    req: Request,
+    commands: &UnboundedSender<Command>,
+    published: &watch::Receiver<Published>,
+) -> Response {
     if req.path == "/v1/status" && matches!(req.method, Method::GET) {
-        return Response::ok(serde_json::json!({ "status": "running" }));
+        if published.has_changed().is_err() {
+            return Response::error(503, "State machine is not running");
+        }
+        let status = &published.borrow().status;
+        return Response::ok(serde_json::json!({
+            "status": "running",
+            "state": status.state,
+            "link": status.link,
+            "portal": status.portal,
+        }));
+    }
+
+    if req.path == "/v1/history" && matches!(req.method, Method::GET) {
+        return Response::ok(serde_json::json!({ "history": published.borrow().history }));
     }
-    
+
+    if req.path == "/v1/logout" && matches!(req.method, Method::POST) {
+        let (reply, outcome) = oneshot::channel();
+        if commands.send(Command::Logout(reply)).is_err() {
+            return Response::error(503, "State machine is not running");
+        }
+        return match outcome.await {
+            Ok(Event::LoggedOut) => Response::ok(serde_json::json!({ "event": "LoggedOut" })),
+            Ok(event) => Response::new(
+                502,
+                Some(serde_json::json!({
+                    "error": "Logout failed",
+                    "event": format!("{:?}", event),
+                })),
+            ),
+            Err(_) => Response::error(503, "State machine dropped the request"),
+        };
+    }
+
+    if req.path == "/v1/reload" && matches!(req.method, Method::POST) {
+        let (reply, outcome) = oneshot::channel();
+        if commands.send(Command::Reload(reply)).is_err() {
+            return Response::error(503, "State machine is not running");
+        }
+        return match outcome.await {
+            Ok(Ok(())) => Response::ok(serde_json::json!({ "reloaded": true })),
+            Ok(Err(e)) => Response::new(
+                422,
+                Some(serde_json::json!({
+                    "error": "Config not reloaded",
+                    "reason": e,
+                })),
+            ),
+            Err(_) => Response::error(503, "State machine dropped the request"),
+        };
+    }
+
+    if (req.path == "/v1/enable" || req.path == "/v1/disable") && matches!(req.method, Method::POST) {
+        let enabled = req.path == "/v1/enable";
+        if commands.send(Command::SetEnabled(enabled)).is_err() {
+            return Response::error(503, "State machine is not running");
+        }
+        return Response::ok(serde_json::json!({ "enabled": enabled }));
+    }
+
     Response::error(404, "Not Found")
 }
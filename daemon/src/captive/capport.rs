@@ -0,0 +1,259 @@
+use log::warn;
+use reqwest::Client;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use serde::{Deserialize, Serialize, Serializer};
+use std::time::{Duration, Instant};
+
+use super::error::CaptiveError;
+
+const MEDIA_TYPE: &str = "application/captive+json";
+
+/// How often the API is asked again while it counts down a data allowance.
+/// It can't say when the data runs out.
+const BYTES_RECHECK: Duration = Duration::from_secs(60);
+
+/// The RFC 8908 Captive Portal API document.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct CapportStatus {
+    pub captive: bool,
+    pub user_portal_url: Option<String>,
+    pub venue_info_url: Option<String>,
+    pub can_extend_session: Option<bool>,
+    pub seconds_remaining: Option<u64>,
+    pub bytes_remaining: Option<u64>,
+}
+
+/// A status document and when we fetched it, so the remaining time can be
+/// counted down between fetches.
+#[derive(Debug, Clone)]
+pub struct CapportState {
+    status: CapportStatus,
+    fetched: Instant,
+}
+
+impl CapportState {
+    /// The status as of now.
+    pub fn current(&self) -> CapportStatus {
+        let mut status = self.status.clone();
+        status.seconds_remaining = self.remaining().map(|left| left.as_secs());
+        status
+    }
+
+    pub fn remaining(&self) -> Option<Duration> {
+        self.status
+            .seconds_remaining
+            .map(|secs| Duration::from_secs(secs).saturating_sub(self.fetched.elapsed()))
+    }
+
+    /// Whether the session ends within `margin`, by time or by data, and the
+    /// portal lets us extend it.
+    pub fn expiring(&self, margin: Duration) -> bool {
+        !self.status.captive
+            && self.status.can_extend_session != Some(false)
+            && (self.remaining().is_some_and(|left| left <= margin)
+                || self.status.bytes_remaining == Some(0))
+    }
+
+    /// How long until the API should be asked again because the session has
+    /// a data allowance left.
+    pub fn recheck_in(&self) -> Option<Duration> {
+        if self.status.captive || self.status.bytes_remaining.is_none() {
+            return None;
+        }
+        Some(BYTES_RECHECK.saturating_sub(self.fetched.elapsed()))
+    }
+
+    pub fn status(&self) -> &CapportStatus {
+        &self.status
+    }
+}
+
+/// Serialized as the status counted down to the moment it is serialized.
+impl Serialize for CapportState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.current().serialize(serializer)
+    }
+}
+
+/// Asks the Captive Portal API at `url` whether we are held captive.
+pub async fn fetch(client: &Client, url: &str) -> Result<CapportState, CaptiveError> {
+    if !url.starts_with("https://") {
+        warn!("captive portal API {} is not served over https", url);
+    }
+    let resp = client.get(url).header(ACCEPT, MEDIA_TYPE).send().await?;
+    if !resp.status().is_success() {
+        return Err(CaptiveError::status(url, resp.status()));
+    }
+    let content_type = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    if !content_type.starts_with(MEDIA_TYPE) && !content_type.starts_with("application/json") {
+        return Err(CaptiveError::unparseable(
+            url,
+            format!("answered with {:?}", content_type),
+        ));
+    }
+    let status: CapportStatus = resp.json().await?;
+    Ok(CapportState {
+        status,
+        fetched: Instant::now(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::captive::test_server::{self, not_found, status};
+
+    fn state(json: &str, age: Duration) -> CapportState {
+        CapportState {
+            status: serde_json::from_str(json).unwrap(),
+            fetched: Instant::now().checked_sub(age).unwrap(),
+        }
+    }
+
+    #[test]
+    fn parses_kebab_case_fields() {
+        let status: CapportStatus = serde_json::from_str(
+            r#"{"captive": false, "user-portal-url": "https://portal.example/",
+                "venue-info-url": "https://venue.example/", "can-extend-session": true,
+                "seconds-remaining": 3600, "bytes-remaining": 1000000}"#,
+        )
+        .unwrap();
+        assert!(!status.captive);
+        assert_eq!(
+            status.user_portal_url.as_deref(),
+            Some("https://portal.example/")
+        );
+        assert_eq!(
+            status.venue_info_url.as_deref(),
+            Some("https://venue.example/")
+        );
+        assert_eq!(status.can_extend_session, Some(true));
+        assert_eq!(status.seconds_remaining, Some(3600));
+        assert_eq!(status.bytes_remaining, Some(1000000));
+
+        let bare: CapportStatus = serde_json::from_str(r#"{"captive": true}"#).unwrap();
+        assert!(bare.captive);
+        assert!(bare.user_portal_url.is_none() && bare.seconds_remaining.is_none());
+        assert!(serde_json::from_str::<CapportStatus>("{}").is_err());
+    }
+
+    #[test]
+    fn counts_down_the_session() {
+        let session = state(
+            r#"{"captive": false, "seconds-remaining": 300}"#,
+            Duration::from_secs(100),
+        );
+        let left = session.remaining().unwrap();
+        assert!(left <= Duration::from_secs(200) && left > Duration::from_secs(195));
+        assert!(session.current().seconds_remaining.unwrap() <= 200);
+        assert!(!session.expiring(Duration::from_secs(60)));
+        assert!(session.expiring(Duration::from_secs(200)));
+
+        let over = state(
+            r#"{"captive": false, "seconds-remaining": 60}"#,
+            Duration::from_secs(120),
+        );
+        assert_eq!(over.remaining(), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn expires_by_data_unless_it_cant_be_extended() {
+        let cases = [
+            (r#"{"captive": false, "bytes-remaining": 0}"#, true),
+            (r#"{"captive": false, "bytes-remaining": 10}"#, false),
+            (
+                r#"{"captive": false, "bytes-remaining": 0, "can-extend-session": false}"#,
+                false,
+            ),
+            (
+                r#"{"captive": false, "seconds-remaining": 10, "can-extend-session": false}"#,
+                false,
+            ),
+            // captive is past expiring
+            (r#"{"captive": true, "seconds-remaining": 0}"#, false),
+            (r#"{"captive": false}"#, false),
+        ];
+        for (json, expected) in cases {
+            let state = state(json, Duration::ZERO);
+            assert_eq!(
+                state.expiring(Duration::from_secs(60)),
+                expected,
+                "{}",
+                json
+            );
+        }
+    }
+
+    #[test]
+    fn rechecks_while_counting_data() {
+        let counting = state(
+            r#"{"captive": false, "bytes-remaining": 10}"#,
+            Duration::from_secs(20),
+        );
+        let due = counting.recheck_in().unwrap();
+        assert!(due <= Duration::from_secs(40) && due > Duration::from_secs(35));
+        let overdue = state(
+            r#"{"captive": false, "bytes-remaining": 10}"#,
+            Duration::from_secs(90),
+        );
+        assert_eq!(overdue.recheck_in(), Some(Duration::ZERO));
+        let timed = state(
+            r#"{"captive": false, "seconds-remaining": 10}"#,
+            Duration::ZERO,
+        );
+        assert_eq!(timed.recheck_in(), None);
+        let captive = state(r#"{"captive": true, "bytes-remaining": 0}"#, Duration::ZERO);
+        assert_eq!(captive.recheck_in(), None);
+    }
+
+    #[tokio::test]
+    async fn fetches_only_captive_json() {
+        let base = test_server::serve(|path| match path {
+            "/captive" => status(
+                "200 OK\r\nContent-Type: application/captive+json; charset=utf-8",
+                r#"{"captive": true, "user-portal-url": "https://portal.example/"}"#,
+            ),
+            "/json" => status(
+                "200 OK\r\nContent-Type: application/json",
+                r#"{"captive": false}"#,
+            ),
+            "/html" => status("200 OK\r\nContent-Type: text/html", r#"{"captive": false}"#),
+            "/untyped" => status("200 OK", r#"{"captive": false}"#),
+            _ => not_found(),
+        })
+        .await;
+        let client = test_server::client();
+        let url = |path: &str| format!("{}{}", base, path);
+
+        let state = fetch(&client, &url("/captive")).await.unwrap();
+        assert!(state.status().captive);
+        assert_eq!(
+            state.status().user_portal_url.as_deref(),
+            Some("https://portal.example/")
+        );
+        assert!(
+            !fetch(&client, &url("/json"))
+                .await
+                .unwrap()
+                .status()
+                .captive
+        );
+        for path in ["/html", "/untyped"] {
+            let err = fetch(&client, &url(path)).await.err().unwrap();
+            assert!(err.to_string().contains("answered with"), "{}", err);
+        }
+        assert!(
+            fetch(&client, &url("/missing"))
+                .await
+                .err()
+                .unwrap()
+                .to_string()
+                .contains("404")
+        );
+    }
+}
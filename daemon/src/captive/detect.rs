@@ -0,0 +1,227 @@
+use log::{debug, info};
+use reqwest::header::LOCATION;
+use reqwest::{Client, StatusCode};
+use tokio::task::JoinSet;
+
+use super::discovery::next_hop;
+use crate::configs::{ProbeEndpoint, VotePolicy};
+
+/// What the probe endpoints agreed on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Open,
+    /// Behind a portal, with the URL to log in at.
+    Captive(String),
+    Offline,
+}
+
+#[derive(Debug)]
+enum Vote {
+    Open,
+    Captive(Option<String>),
+    Unreachable,
+}
+
+/// Checks every endpoint concurrently and lets `policy` decide between them.
+/// `client` must not follow redirects, the redirect is the portal.
+///
+/// Dropping the future aborts the probes still in flight.
+pub async fn probe_all(
+    client: &Client,
+    endpoints: &[ProbeEndpoint],
+    policy: VotePolicy,
+) -> Verdict {
+    let mut probes = JoinSet::new();
+    for (idx, endpoint) in endpoints.iter().enumerate() {
+        let client = client.clone();
+        let endpoint = endpoint.clone();
+        probes.spawn(async move { (idx, vote(&client, &endpoint).await) });
+    }
+    let mut votes: Vec<Vote> = endpoints.iter().map(|_| Vote::Unreachable).collect();
+    while let Some(joined) = probes.join_next().await {
+        if let Ok((idx, vote)) = joined {
+            votes[idx] = vote;
+        }
+    }
+    for (endpoint, vote) in endpoints.iter().zip(&votes) {
+        debug!("probe {} voted {:?}", endpoint.url, vote);
+    }
+    let verdict = tally(endpoints, &votes, policy);
+    info!("probe verdict ({:?} policy): {:?}", policy, verdict);
+    verdict
+}
+
+async fn vote(client: &Client, endpoint: &ProbeEndpoint) -> Vote {
+    let resp = match client.get(&endpoint.url).send().await {
+        Ok(resp) => resp,
+        Err(e) => {
+            debug!("probe {} failed: {}", endpoint.url, e);
+            return Vote::Unreachable;
+        }
+    };
+    let status = resp.status();
+    if status.is_redirection() {
+        let location = resp
+            .headers()
+            .get(LOCATION)
+            .and_then(|loc| loc.to_str().ok())
+            .and_then(|loc| resp.url().join(loc).ok())
+            .map(|url| url.to_string());
+        return Vote::Captive(location);
+    }
+    let base = resp.url().clone();
+    let body = resp.text().await.unwrap_or_default();
+    let body_matches = endpoint
+        .body
+        .as_ref()
+        .is_none_or(|expected| body.contains(expected.as_str()));
+    if status.as_u16() == endpoint.status && body_matches {
+        Vote::Open
+    } else if status.is_success() || status == StatusCode::NETWORK_AUTHENTICATION_REQUIRED {
+        // the page served in place of the probe response may point at the portal
+        Vote::Captive(next_hop(&base, &body).map(|url| url.to_string()))
+    } else {
+        Vote::Unreachable
+    }
+}
+
+fn tally(endpoints: &[ProbeEndpoint], votes: &[Vote], policy: VotePolicy) -> Verdict {
+    let open = votes.iter().filter(|v| matches!(v, Vote::Open)).count();
+    let captive = votes
+        .iter()
+        .filter(|v| matches!(v, Vote::Captive(_)))
+        .count();
+    if open + captive == 0 {
+        return Verdict::Offline;
+    }
+    let is_captive = match policy {
+        VotePolicy::Any => captive > 0,
+        // a tie usually means some endpoints are whitelisted by the portal
+        VotePolicy::Majority => captive > 0 && captive >= open,
+        VotePolicy::All => captive > 0 && open == 0,
+    };
+    if !is_captive {
+        return Verdict::Open;
+    }
+    let portal = votes
+        .iter()
+        .find_map(|v| match v {
+            Vote::Captive(Some(url)) => Some(url.clone()),
+            _ => None,
+        })
+        .or_else(|| {
+            // the portal answered in place of the endpoint without pointing elsewhere
+            endpoints
+                .iter()
+                .zip(votes)
+                .find(|(_, v)| matches!(v, Vote::Captive(None)))
+                .map(|(endpoint, _)| endpoint.url.clone())
+        });
+    match portal {
+        Some(url) => Verdict::Captive(url),
+        None => Verdict::Offline,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::captive::test_server::{self, page, redirect, status};
+
+    fn endpoints(n: usize) -> Vec<ProbeEndpoint> {
+        (0..n)
+            .map(|i| ProbeEndpoint {
+                url: format!("http://probe{}.example/", i),
+                status: 204,
+                body: None,
+            })
+            .collect()
+    }
+
+    fn portal(url: &str) -> Vote {
+        Vote::Captive(Some(url.to_string()))
+    }
+
+    #[test]
+    fn tallies_votes() {
+        use VotePolicy::{All, Any, Majority};
+        let captive = |url: &str| Verdict::Captive(url.to_string());
+        let cases = [
+            (vec![], Any, Verdict::Offline),
+            (
+                vec![Vote::Unreachable, Vote::Unreachable],
+                Majority,
+                Verdict::Offline,
+            ),
+            (vec![Vote::Open, Vote::Unreachable], All, Verdict::Open),
+            (
+                vec![Vote::Open, portal("http://p/")],
+                Any,
+                captive("http://p/"),
+            ),
+            // a tie goes to the portal
+            (
+                vec![Vote::Open, portal("http://p/")],
+                Majority,
+                captive("http://p/"),
+            ),
+            (vec![Vote::Open, portal("http://p/")], All, Verdict::Open),
+            (
+                vec![Vote::Open, Vote::Open, portal("http://p/")],
+                Majority,
+                Verdict::Open,
+            ),
+            (
+                vec![Vote::Unreachable, portal("http://p/"), Vote::Unreachable],
+                All,
+                captive("http://p/"),
+            ),
+            // a portal that names itself beats one that doesn't
+            (
+                vec![Vote::Captive(None), portal("http://p/")],
+                Any,
+                captive("http://p/"),
+            ),
+            // otherwise the endpoint it answered in place of
+            (
+                vec![Vote::Open, Vote::Captive(None)],
+                Majority,
+                captive("http://probe1.example/"),
+            ),
+        ];
+        for (votes, policy, expected) in cases {
+            let verdict = tally(&endpoints(votes.len()), &votes, policy);
+            assert_eq!(verdict, expected, "{:?} {:?}", votes, policy);
+        }
+    }
+
+    #[tokio::test]
+    async fn votes_on_responses() {
+        let base = test_server::serve(|path| match path {
+            "/generate_204" => status("204 No Content", ""),
+            "/redirect" => redirect("/login?x=1"),
+            "/refresh" => page(r#"<meta http-equiv="refresh" content="0; url=http://portal/">"#),
+            "/hello" => page("hello"),
+            _ => status("500 Internal Server Error", ""),
+        })
+        .await;
+        let client = test_server::client();
+        let cases = [
+            ("/generate_204", 204, None, "Open"),
+            ("/redirect", 204, None, "Captive(Some(\"BASE/login?x=1\"))"),
+            ("/refresh", 204, None, "Captive(Some(\"http://portal/\"))"),
+            ("/hello", 200, Some("hello"), "Open"),
+            ("/hello", 200, Some("success"), "Captive(None)"),
+            ("/nope", 204, None, "Unreachable"),
+        ];
+        for (path, status, body, expected) in cases {
+            let endpoint = ProbeEndpoint {
+                url: format!("{}{}", base, path),
+                status,
+                body: body.map(str::to_string),
+            };
+            let voted = format!("{:?}", vote(&client, &endpoint).await);
+            assert_eq!(voted, expected.replace("BASE", &base), "{}", path);
+        }
+    }
+}
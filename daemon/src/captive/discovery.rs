@@ -0,0 +1,213 @@
+use log::{debug, info};
+use regex::Regex;
+use reqwest::header::LOCATION;
+use reqwest::{Client, Url};
+use scraper::{Html, Selector};
+use std::sync::LazyLock;
+
+use super::drivers::LoginPage;
+use super::error::CaptiveError;
+
+/// Portals rarely need more than three or four hops to reach the login page.
+const MAX_HOPS: usize = 10;
+
+static JS_REDIRECT_RES: LazyLock<Vec<Regex>> = LazyLock::new(|| {
+    [
+        r#"(?:window\.|document\.|top\.|self\.)?location(?:\.href)?\s*=\s*['"](?P<url>[^'"]+)['"]"#,
+        r#"location\.(?:replace|assign)\(\s*['"](?P<url>[^'"]+)['"]\s*\)"#,
+    ]
+    .iter()
+    .map(|re| Regex::new(re).unwrap())
+    .collect()
+});
+
+/// Follows `start` through HTTP redirects, `<meta http-equiv="refresh">` and
+/// JavaScript redirects until it reaches a page that doesn't send us further.
+///
+/// `client` must not follow redirects itself, otherwise the chain is lost.
+pub async fn discover(client: &Client, start: &str) -> Result<LoginPage, CaptiveError> {
+    let mut url = Url::parse(start).map_err(|e| CaptiveError::unparseable(start, e))?;
+    let mut chain: Vec<Url> = Vec::new();
+    loop {
+        if chain.len() >= MAX_HOPS {
+            return Err(CaptiveError::unparseable(
+                start,
+                format!("redirected more than {} times", MAX_HOPS),
+            ));
+        }
+        chain.push(url.clone());
+        let resp = client.get(url.clone()).send().await?;
+        let status = resp.status();
+        let next = if status.is_redirection() {
+            let location = resp
+                .headers()
+                .get(LOCATION)
+                .and_then(|loc| loc.to_str().ok())
+                .ok_or_else(|| {
+                    CaptiveError::unparseable(&url, format!("{} without a Location", status))
+                })?;
+            url.join(location)
+                .map_err(|e| CaptiveError::unparseable(&url, e))?
+        } else if status.is_success() {
+            let headers = resp.headers().clone();
+            let body = resp.text().await.unwrap_or_default();
+            let Some(next) = next_hop(&url, &body) else {
+                let page = LoginPage {
+                    url: url.to_string(),
+                    headers,
+                    body,
+                    chain: chain.iter().map(Url::to_string).collect(),
+                };
+                info!("portal login page {} via {:?}", page.url, page.hosts());
+                return Ok(page);
+            };
+            next
+        } else {
+            return Err(CaptiveError::status(url, status));
+        };
+        if chain.contains(&next) {
+            return Err(CaptiveError::unparseable(
+                &url,
+                format!("redirect loop back to {}", next),
+            ));
+        }
+        debug!("portal hop {} -> {}", url, next);
+        url = next;
+    }
+}
+
+/// Where a page that isn't a login form sends the browser next, if anywhere.
+pub fn next_hop(base: &Url, body: &str) -> Option<Url> {
+    let doc = Html::parse_document(body);
+    // a page with a form is where we log in, whatever its scripts do afterwards
+    if doc
+        .select(&Selector::parse("form").unwrap())
+        .next()
+        .is_some()
+    {
+        return None;
+    }
+    let target = meta_refresh(&doc).or_else(|| {
+        JS_REDIRECT_RES
+            .iter()
+            .find_map(|re| re.captures(body).map(|caps| caps["url"].to_string()))
+    })?;
+    base.join(target.trim()).ok()
+}
+
+fn meta_refresh(doc: &Html) -> Option<String> {
+    let meta_sel = Selector::parse("meta[http-equiv]").unwrap();
+    doc.select(&meta_sel)
+        .filter(|meta| {
+            meta.value()
+                .attr("http-equiv")
+                .is_some_and(|equiv| equiv.eq_ignore_ascii_case("refresh"))
+        })
+        .find_map(|meta| {
+            // content="5; url='https://portal/login'"
+            let content = meta.value().attr("content")?;
+            let (_, target) = content.split_once(';')?;
+            let (key, url) = target.trim().split_once('=')?;
+            if !key.trim().eq_ignore_ascii_case("url") {
+                return None;
+            }
+            Some(
+                url.trim()
+                    .trim_matches(|c| c == '\'' || c == '"')
+                    .to_string(),
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::captive::test_server::{client, not_found, page, redirect, serve};
+
+    #[test]
+    fn finds_the_next_hop() {
+        let base = Url::parse("http://gw.example/portal/index.html").unwrap();
+        let cases = [
+            (
+                r#"<meta http-equiv="refresh" content="0; url=login.php?a=1">"#,
+                Some("http://gw.example/portal/login.php?a=1"),
+            ),
+            (
+                r#"<META HTTP-EQUIV="Refresh" CONTENT="5;URL='https://portal.example/'">"#,
+                Some("https://portal.example/"),
+            ),
+            (r#"<meta http-equiv="refresh" content="30">"#, None),
+            (
+                r#"<script>window.location.href = "/splash";</script>"#,
+                Some("http://gw.example/splash"),
+            ),
+            (
+                r#"<script>top.location='http://portal.example/auth'</script>"#,
+                Some("http://portal.example/auth"),
+            ),
+            (
+                r#"<script>location.replace( '../login' )</script>"#,
+                Some("http://gw.example/login"),
+            ),
+            // the login form is where we stop, whatever its scripts do
+            (
+                r#"<form action="/login"></form><script>location.href="/x"</script>"#,
+                None,
+            ),
+            ("<p>It works!</p>", None),
+        ];
+        for (body, expected) in cases {
+            let next = next_hop(&base, body).map(|url| url.to_string());
+            assert_eq!(next.as_deref(), expected, "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn follows_every_kind_of_redirect() {
+        let base = serve(|path| match path {
+            "/probe" => redirect("/splash"),
+            "/splash" => page(r#"<meta http-equiv="refresh" content="0; url=/js">"#),
+            "/js" => page(r#"<script>location.href = "/login";</script>"#),
+            "/login" => page(r#"<form action="/auth"><input type="password"></form>"#),
+            _ => not_found(),
+        })
+        .await;
+        let page = discover(&client(), &format!("{}/probe", base))
+            .await
+            .unwrap();
+        assert_eq!(page.url, format!("{}/login", base));
+        let chain: Vec<String> = ["/probe", "/splash", "/js", "/login"]
+            .iter()
+            .map(|path| format!("{}{}", base, path))
+            .collect();
+        assert_eq!(page.chain, chain);
+    }
+
+    #[tokio::test]
+    async fn stops_at_loops_and_long_chains() {
+        let base = serve(|path| match path {
+            "/a" => redirect("/b"),
+            "/b" => page(r#"<script>location.replace("/a")</script>"#),
+            _ => match path
+                .strip_prefix("/hop/")
+                .and_then(|n| n.parse::<u32>().ok())
+            {
+                Some(n) => redirect(&format!("/hop/{}", n + 1)),
+                None => not_found(),
+            },
+        })
+        .await;
+        let cases = [
+            ("/a", "redirect loop back to"),
+            ("/hop/0", "redirected more than 10 times"),
+            ("/missing", "404"),
+        ];
+        for (path, expected) in cases {
+            let err = discover(&client(), &format!("{}{}", base, path))
+                .await
+                .err()
+                .unwrap();
+            assert!(err.to_string().contains(expected), "{}: {}", path, err);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use async_trait::async_trait;
+use log::{debug, warn};
+
+use super::{LoginOutcome, LoginPage, PortalDriver};
+use crate::captive::error::CaptiveError;
+use crate::captive::form::LoginForm;
+use crate::captive::session::HttpSession;
+use crate::configs::Profile;
+use crate::event::Event;
+
+/// For portals that only want their terms of service accepted: ticks the
+/// boxes on the acceptance form and submits it.
+pub struct ClickThroughDriver;
+
+impl ClickThroughDriver {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[async_trait]
+impl PortalDriver for ClickThroughDriver {
+    fn name(&self) -> &str {
+        "click-through"
+    }
+
+    fn supports(&self, profile: &Profile) -> bool {
+        matches!(profile, Profile::ClickThrough { accept_terms: true })
+    }
+
+    fn detect(&self, page: &LoginPage) -> bool {
+        LoginForm::find_terms(&page.url, &page.body).is_some()
+    }
+
+    async fn login(
+        &self,
+        http: &HttpSession,
+        page: &LoginPage,
+        _profile: &Profile,
+    ) -> Result<LoginOutcome, CaptiveError> {
+        let Some(mut form) = LoginForm::find_terms(&page.url, &page.body) else {
+            return Err(CaptiveError::MissingForm(page.url.clone()));
+        };
+        let ticked = form.accept_terms();
+        if !form.unchecked.is_empty() {
+            warn!(
+                "leaving {:?} unticked on {}",
+                form.unchecked
+                    .iter()
+                    .map(|checkbox| &checkbox.name)
+                    .collect::<Vec<_>>(),
+                page.url
+            );
+        }
+        debug!("ticked {} boxes, submitting to {}", ticked, form.action);
+        let res = form.submit(&http.client).await?;
+        if !res.status().is_success() {
+            return Err(CaptiveError::status(&form.action, res.status()));
+        }
+        let page = LoginPage::from_response(res).await;
+        if self.detect(&page) {
+            // the terms page again, something we didn't tick or fill
+            return Ok(Event::Unknown.into());
+        }
+        Ok(self.classify_response(&page.body).into())
+    }
+
+    /// Whether the terms were accepted is only known by probing again.
+    fn classify_response(&self, _body: &str) -> Event {
+        Event::Success
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(body: &str) -> LoginPage {
+        let url = "http://portal.example/welcome".to_string();
+        LoginPage {
+            chain: vec![url.clone()],
+            url,
+            headers: Default::default(),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn only_takes_profiles_that_accept_the_terms() {
+        let driver = ClickThroughDriver::new();
+        let cases = [
+            ("accept_terms = true", true),
+            ("accept_terms = false", false),
+            ("voucher = \"1234\"", false),
+            ("rollno = \"jdoe\"\npassword = \"hunter2\"", false),
+        ];
+        for (profile, expected) in cases {
+            let profile: Profile = toml::from_str(profile).unwrap();
+            assert_eq!(driver.supports(&profile), expected, "{:?}", profile);
+        }
+    }
+
+    #[test]
+    fn detects_terms_pages() {
+        let driver = ClickThroughDriver::new();
+        let cases = [
+            (
+                r#"<form action="/accept"><input type="checkbox" name="tos"><button>Go</button></form>"#,
+                true,
+            ),
+            (
+                r#"<form action="/go"><p>I accept the Terms of Use</p><input type="submit"></form>"#,
+                true,
+            ),
+            (
+                r#"<form action="/login"><input name="user"><input type="password" name="pw"></form>"#,
+                false,
+            ),
+            ("<p>You are now connected.</p>", false),
+        ];
+        for (body, expected) in cases {
+            assert_eq!(driver.detect(&page(body)), expected, "{}", body);
+        }
+    }
+}
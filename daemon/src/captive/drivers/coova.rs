@@ -0,0 +1,359 @@
+use async_trait::async_trait;
+use log::{debug, info, warn};
+use reqwest::Client;
+use reqwest::Url;
+use reqwest::header::LOCATION;
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+use super::{LoginOutcome, LoginPage, PortalDriver, PortalSession};
+use crate::captive::error::CaptiveError;
+use crate::captive::session::HttpSession;
+use crate::configs::Profile;
+use crate::event::Event;
+
+/// CoovaChilli / ChilliSpot UAM. The portal redirect names the chilli
+/// gateway (`uamip`, `uamport`) and a `challenge`; we log on at the gateway
+/// directly rather than through the UAM server's login page.
+///
+/// The password is PAP-encoded against the bare challenge, hotspots that
+/// require a UAM secret are not supported.
+pub struct CoovaDriver;
+
+/// What the portal redirect told us about the chilli gateway.
+struct Uam {
+    gateway: Url,
+    challenge: Vec<u8>,
+    userurl: Option<String>,
+}
+
+impl CoovaDriver {
+    pub fn new() -> Self {
+        Self
+    }
+
+    fn uam(&self, page: &LoginPage) -> Option<Uam> {
+        page.chain
+            .iter()
+            .chain(std::iter::once(&page.url))
+            .filter_map(|url| Url::parse(url).ok())
+            .find_map(|url| {
+                let param = |name: &str| {
+                    url.query_pairs()
+                        .find(|(key, _)| key == name)
+                        .map(|(_, value)| value.into_owned())
+                };
+                let gateway = format!("http://{}:{}/", param("uamip")?, param("uamport")?);
+                Some(Uam {
+                    gateway: Url::parse(&gateway).ok()?,
+                    challenge: decode_hex(&param("challenge")?).filter(|c| !c.is_empty())?,
+                    userurl: param("userurl"),
+                })
+            })
+    }
+
+    /// The gateway's JSON status, if it says we are logged in.
+    async fn status(&self, client: &Client, url: &str) -> Result<Option<Value>, CaptiveError> {
+        let resp = client.get(url).send().await?;
+        if !resp.status().is_success() {
+            return Err(CaptiveError::status(url, resp.status()));
+        }
+        let status: Value = resp.json().await?;
+        debug!("chilli status: {}", status);
+        Ok((status["clientState"].as_i64() == Some(1)).then_some(status))
+    }
+}
+
+#[async_trait]
+impl PortalDriver for CoovaDriver {
+    fn name(&self) -> &str {
+        "coovachilli"
+    }
+
+    fn detect(&self, page: &LoginPage) -> bool {
+        self.uam(page).is_some()
+    }
+
+    async fn login(
+        &self,
+        http: &HttpSession,
+        page: &LoginPage,
+        profile: &Profile,
+    ) -> Result<LoginOutcome, CaptiveError> {
+        let Some((username, password)) = profile.credentials() else {
+            return Ok(Event::Unknown.into());
+        };
+        let Some(uam) = self.uam(page) else {
+            return Err(CaptiveError::unparseable(&page.url, "no UAM parameters"));
+        };
+        let mut logon = uam.gateway.join("logon").unwrap();
+        logon
+            .query_pairs_mut()
+            .append_pair("username", username)
+            .append_pair("password", &pap_password(password, &uam.challenge));
+        if let Some(userurl) = &uam.userurl {
+            logon.query_pairs_mut().append_pair("userurl", userurl);
+        }
+        debug!("logging on at {}", uam.gateway);
+        // chilli redirects to the UAM server with the outcome in `res=`, the
+        // UAM server itself may well be out of reach yet
+        let res = http.no_redirect.get(logon.clone()).send().await?;
+        let event = match res
+            .headers()
+            .get(LOCATION)
+            .and_then(|loc| loc.to_str().ok())
+        {
+            Some(location) if res.status().is_redirection() => logon
+                .join(location)
+                .map_or(Event::Unknown, |url| self.classify_response(url.as_str())),
+            _ => {
+                warn!(
+                    "chilli answered the logon with {}, not a redirect",
+                    res.status()
+                );
+                Event::Unknown
+            }
+        };
+        let status_url = uam.gateway.join("json/status").unwrap().to_string();
+        let mut session = PortalSession {
+            keepalive_url: Some(status_url.clone()),
+            logout_url: uam.gateway.join("logoff").ok().map(|url| url.to_string()),
+            ..Default::default()
+        };
+        if !matches!(event, Event::Success) {
+            return Ok(LoginOutcome { event, session });
+        }
+        let status = match self.status(&http.client, &status_url).await {
+            Ok(Some(status)) => status,
+            Ok(None) => {
+                warn!("chilli accepted the logon but its status says we are not logged in");
+                return Ok(Event::Unknown.into());
+            }
+            Err(e) => {
+                warn!("chilli accepted the logon, not knowing when it ends: {}", e);
+                return Ok(LoginOutcome { event, session });
+            }
+        };
+        let timeout = status["session"]["sessionTimeout"]
+            .as_u64()
+            .filter(|&t| t > 0);
+        let elapsed = status["accounting"]["sessionTime"].as_u64().unwrap_or(0);
+        if let Some(timeout) = timeout {
+            let remaining = Duration::from_secs(timeout.saturating_sub(elapsed));
+            info!("chilli session ends in {:?}", remaining);
+            session.expires = Some(Instant::now() + remaining);
+        }
+        Ok(LoginOutcome { event, session })
+    }
+
+    async fn keepalive(
+        &self,
+        client: &Client,
+        session: &PortalSession,
+    ) -> Result<Event, CaptiveError> {
+        let Some(url) = &session.keepalive_url else {
+            return Ok(Event::SessionExpired);
+        };
+        match self.status(client, url).await? {
+            Some(_) => Ok(Event::KeepAlive),
+            None => Ok(Event::SessionExpired),
+        }
+    }
+
+    /// Classifies the UAM server URL chilli sends us to after the logon.
+    fn classify_response(&self, body: &str) -> Event {
+        let params: Vec<(String, String)> = Url::parse(body)
+            .map(|url| url.query_pairs().into_owned().collect())
+            .unwrap_or_default();
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        match param("res") {
+            Some("success" | "already") => Event::Success,
+            Some("failed") => {
+                let reply = param("reply").unwrap_or("").to_ascii_lowercase();
+                if reply.contains("concurrent") || reply.contains("already") {
+                    Event::MaxConcurrent
+                } else {
+                    Event::WrongCreds
+                }
+            }
+            _ => Event::Unknown,
+        }
+    }
+}
+
+/// The password XORed with the challenge, 16 bytes at a time, as chilli's
+/// `hotspotlogin.cgi` sends it.
+fn pap_password(password: &str, challenge: &[u8]) -> String {
+    let mut bytes = password.as_bytes().to_vec();
+    bytes.resize(bytes.len().div_ceil(16).max(1) * 16, 0);
+    bytes
+        .iter()
+        .zip(challenge.iter().cycle())
+        .map(|(byte, key)| format!("{:02x}", byte ^ key))
+        .collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::captive::test_server::{self, Reply, not_found, page, redirect, status};
+
+    const CHALLENGE: &str = "bb26c86501d23f9ea9424f98d40f6527";
+
+    #[test]
+    fn encodes_pap_passwords() {
+        let challenge = decode_hex(CHALLENGE).unwrap();
+        let cases = [
+            ("", "bb26c86501d23f9ea9424f98d40f6527"),
+            ("secret", "c843ab1764a63f9ea9424f98d40f6527"),
+            ("0123456789abcdef", "8b17fa5635e709a9917b2efab76b0041"),
+            (
+                "a-much-longer-password-123",
+                "da0ba51062ba12f2c62c28fda6221546c855bf0a73b612af9b714f98d40f6527",
+            ),
+        ];
+        for (password, expected) in cases {
+            assert_eq!(pap_password(password, &challenge), expected, "{}", password);
+        }
+    }
+
+    #[test]
+    fn decodes_hex() {
+        assert_eq!(decode_hex("00ff7A"), Some(vec![0x00, 0xff, 0x7a]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+
+    #[test]
+    fn classifies_the_uam_redirect() {
+        let driver = CoovaDriver::new();
+        let cases = [
+            (
+                "http://uam.example/?res=success&uamip=10.1.0.1",
+                Event::Success,
+            ),
+            ("http://uam.example/?res=already", Event::Success),
+            ("http://uam.example/?res=failed", Event::WrongCreds),
+            (
+                "http://uam.example/?res=failed&reply=Invalid+password",
+                Event::WrongCreds,
+            ),
+            (
+                "http://uam.example/?res=failed&reply=Concurrent+logins+exceeded",
+                Event::MaxConcurrent,
+            ),
+            (
+                "http://uam.example/?res=failed&reply=You+are+ALREADY+logged+in",
+                Event::MaxConcurrent,
+            ),
+            (
+                "http://uam.example/?res=notyet&challenge=00",
+                Event::Unknown,
+            ),
+            ("http://uam.example/", Event::Unknown),
+            ("not a url", Event::Unknown),
+        ];
+        for (landed, expected) in cases {
+            assert_eq!(driver.classify_response(landed), expected, "{}", landed);
+        }
+    }
+
+    const LOGON: &str = "/logon?username=jdoe&password=c843ab1764a63f9ea9424f98d40f6527&userurl=http%3A%2F%2Fexample.com%2F";
+    const ACCEPTED: &str = "http://uam.invalid/hotspotlogin?res=success&uamip=127.0.0.1";
+
+    /// Logs on at a gateway answering like `route`.
+    async fn log_on(route: fn(&str) -> Reply) -> LoginOutcome {
+        let base = test_server::serve(route).await;
+        let port = base.rsplit(':').next().unwrap();
+        let url = format!(
+            "http://uam.invalid/login?uamip=127.0.0.1&uamport={}&challenge={}&userurl=http://example.com/",
+            port, CHALLENGE
+        );
+        let page = LoginPage {
+            chain: vec![url.clone()],
+            url,
+            headers: Default::default(),
+            body: String::new(),
+        };
+        let profile = toml::from_str("rollno = \"jdoe\"\npassword = \"secret\"").unwrap();
+        let http = HttpSession::new(Duration::from_secs(5));
+        CoovaDriver::new()
+            .login(&http, &page, &profile)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reads_the_outcome_from_the_logon_redirect() {
+        let outcome = log_on(|path| match path {
+            LOGON => redirect(ACCEPTED),
+            "/json/status" => page(
+                r#"{"clientState":1,"session":{"sessionTimeout":3600},"accounting":{"sessionTime":600}}"#,
+            ),
+            _ => not_found(),
+        })
+        .await;
+        assert_eq!(outcome.event, Event::Success);
+        let left = outcome.session.expires.unwrap() - Instant::now();
+        assert!(left > Duration::from_secs(2990) && left <= Duration::from_secs(3000));
+        assert!(outcome.session.logout_url.unwrap().ends_with("/logoff"));
+
+        let outcome = log_on(|path| match path {
+            LOGON => redirect("http://uam.invalid/hotspotlogin?res=failed&reply=Bad+password"),
+            _ => not_found(),
+        })
+        .await;
+        assert_eq!(outcome.event, Event::WrongCreds);
+
+        let outcome = log_on(|path| match path {
+            LOGON => page("<p>Logged in</p>"),
+            _ => not_found(),
+        })
+        .await;
+        assert_eq!(outcome.event, Event::Unknown);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_session_without_a_status() {
+        let unreadable: [fn(&str) -> Reply; 2] = [
+            |path| match path {
+                LOGON => redirect(ACCEPTED),
+                _ => not_found(),
+            },
+            |path| match path {
+                LOGON => redirect(ACCEPTED),
+                "/json/status" => status("200 OK", "<html>not json</html>"),
+                _ => not_found(),
+            },
+        ];
+        for route in unreadable {
+            let outcome = log_on(route).await;
+            assert_eq!(outcome.event, Event::Success);
+            assert!(outcome.session.expires.is_none());
+            assert!(outcome.session.keepalive_url.is_some());
+        }
+
+        let outcome = log_on(|path| match path {
+            LOGON => redirect(ACCEPTED),
+            "/json/status" => page(r#"{"clientState":0}"#),
+            _ => not_found(),
+        })
+        .await;
+        assert_eq!(outcome.event, Event::Unknown);
+    }
+}
@@ -0,0 +1,369 @@
+use anyhow::{Context, anyhow};
+use async_trait::async_trait;
+use log::{debug, info, warn};
+use regex::Regex;
+use reqwest::Client;
+use scraper::Selector;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+use super::{LoginOutcome, LoginPage, PortalDriver};
+use crate::captive::error::CaptiveError;
+use crate::captive::form::LoginForm;
+use crate::captive::session::HttpSession;
+use crate::configs::Profile;
+use crate::event::Event;
+
+/// A captive portal described in a TOML file rather than in Rust.
+#[derive(Deserialize, Debug)]
+pub struct PortalDefinition {
+    pub name: String,
+    pub detect: DetectRules,
+    pub form: FormRules,
+    pub responses: ResponseRules,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DetectRules {
+    pub url: Option<String>,
+    pub body: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FormRules {
+    #[serde(default = "default_form_selector")]
+    pub selector: String,
+    pub username: String,
+    pub password: String,
+    /// Fields the form has to carry for the login to work. Every field of
+    /// the form is submitted, but without one of these the login isn't tried.
+    #[serde(default)]
+    pub hidden: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ResponseRules {
+    pub success: String,
+    pub wrong_credentials: Option<String>,
+    pub max_concurrent: Option<String>,
+}
+
+fn default_form_selector() -> String {
+    String::from("form")
+}
+
+impl PortalDefinition {
+    pub fn parse(toml_str: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(toml_str)?)
+    }
+}
+
+pub struct DeclarativeDriver {
+    name: String,
+    detect_url: Option<Regex>,
+    detect_body: Option<Regex>,
+    form_selector: Selector,
+    username_field: String,
+    password_field: String,
+    hidden_fields: Vec<String>,
+    success_regex: Regex,
+    auth_failed_regex: Option<Regex>,
+    max_concurrent_regex: Option<Regex>,
+}
+
+impl DeclarativeDriver {
+    pub fn new(def: PortalDefinition) -> anyhow::Result<Self> {
+        if def.detect.url.is_none() && def.detect.body.is_none() {
+            return Err(anyhow!("portal '{}' has no detect rules", def.name));
+        }
+        let form_selector = Selector::parse(&def.form.selector)
+            .map_err(|e| anyhow!("invalid form selector '{}': {:?}", def.form.selector, e))?;
+        Ok(Self {
+            detect_url: compile(def.detect.url.as_deref())?,
+            detect_body: compile(def.detect.body.as_deref())?,
+            form_selector,
+            username_field: def.form.username,
+            password_field: def.form.password,
+            hidden_fields: def.form.hidden,
+            success_regex: Regex::new(&def.responses.success)?,
+            auth_failed_regex: compile(def.responses.wrong_credentials.as_deref())?,
+            max_concurrent_regex: compile(def.responses.max_concurrent.as_deref())?,
+            name: def.name,
+        })
+    }
+
+    pub fn from_toml(toml_str: &str) -> anyhow::Result<Self> {
+        Self::new(PortalDefinition::parse(toml_str)?)
+    }
+
+    /// Fills in and submits the login form, returning the body of the page
+    /// the portal answered with.
+    pub async fn submit(
+        &self,
+        client: &Client,
+        page: &LoginPage,
+        username: &str,
+        password: &str,
+    ) -> Result<String, CaptiveError> {
+        let Some(mut form) = LoginForm::select(&page.url, &page.body, &self.form_selector) else {
+            warn!("[{}] no form matches the configured selector", self.name);
+            return Err(CaptiveError::MissingForm(page.url.clone()));
+        };
+        let missing: Vec<&String> = self
+            .hidden_fields
+            .iter()
+            .filter(|field| form.get(field).is_none())
+            .collect();
+        if !missing.is_empty() {
+            warn!("[{}] login form is missing {:?}", self.name, missing);
+            return Err(CaptiveError::unparseable(
+                &page.url,
+                format!("login form has no {:?}", missing),
+            ));
+        }
+        form.set(&self.username_field, username);
+        form.set(&self.password_field, password);
+        debug!("[{}] submitting login form to {}", self.name, form.action);
+        let res = form.submit(client).await?;
+        if !res.status().is_success() {
+            return Err(CaptiveError::status(&form.action, res.status()));
+        }
+        Ok(res.text().await.unwrap_or_default())
+    }
+}
+
+#[async_trait]
+impl PortalDriver for DeclarativeDriver {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn detect(&self, page: &LoginPage) -> bool {
+        self.detect_url
+            .as_ref()
+            .is_some_and(|re| re.is_match(&page.url))
+            || self
+                .detect_body
+                .as_ref()
+                .is_some_and(|re| re.is_match(&page.body))
+    }
+
+    async fn login(
+        &self,
+        http: &HttpSession,
+        page: &LoginPage,
+        profile: &Profile,
+    ) -> Result<LoginOutcome, CaptiveError> {
+        let Some((username, password)) = profile.credentials() else {
+            return Ok(Event::Unknown.into());
+        };
+        let login_status_page_html = self.submit(&http.client, page, username, password).await?;
+        Ok(self.classify_response(&login_status_page_html).into())
+    }
+
+    fn classify_response(&self, body: &str) -> Event {
+        if self.success_regex.is_match(body) {
+            Event::Success
+        } else if matches(&self.auth_failed_regex, body) {
+            Event::WrongCreds
+        } else if matches(&self.max_concurrent_regex, body) {
+            Event::MaxConcurrent
+        } else {
+            Event::Unknown
+        }
+    }
+}
+
+fn compile(pattern: Option<&str>) -> anyhow::Result<Option<Regex>> {
+    pattern
+        .map(|p| Regex::new(p).with_context(|| format!("invalid pattern '{}'", p)))
+        .transpose()
+}
+
+fn matches(re: &Option<Regex>, body: &str) -> bool {
+    re.as_ref().is_some_and(|re| re.is_match(body))
+}
+
+/// Loads every `*.toml` portal definition in `dir`. Files that fail to parse
+/// are logged and skipped so one bad definition doesn't take the daemon down.
+pub fn load_dir(dir: &Path) -> Vec<Box<dyn PortalDriver>> {
+    let mut drivers: Vec<Box<dyn PortalDriver>> = Vec::new();
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            debug!("not loading portal definitions from {:?}: {}", dir, e);
+            return drivers;
+        }
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+    for path in paths {
+        let loaded = fs::read_to_string(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|s| DeclarativeDriver::from_toml(&s));
+        match loaded {
+            Ok(driver) => {
+                info!("loaded portal definition '{}' from {:?}", driver.name, path);
+                drivers.push(Box::new(driver));
+            }
+            Err(e) => warn!("skipping portal definition {:?}: {:#}", path, e),
+        }
+    }
+    drivers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::captive::test_server::{self, not_found, page};
+    use std::time::Duration;
+
+    const CAMPUS: &str = r#"
+name = "campus"
+
+[detect]
+url = 'login\.campus\.edu'
+body = 'Campus Wi-Fi'
+
+[form]
+selector = "form#login"
+username = "user"
+password = "pass"
+hidden = ["token"]
+
+[responses]
+success = 'You are now connected'
+wrong_credentials = 'Invalid username or password'
+max_concurrent = 'Too many sessions'
+"#;
+
+    fn login_page(url: &str, body: &str) -> LoginPage {
+        LoginPage {
+            chain: vec![url.to_string()],
+            url: url.to_string(),
+            headers: Default::default(),
+            body: body.to_string(),
+        }
+    }
+
+    fn credentials() -> Profile {
+        toml::from_str("rollno = \"jdoe\"\npassword = \"secret\"").unwrap()
+    }
+
+    #[test]
+    fn rejects_broken_definitions() {
+        let cases = [
+            ("name = \"x\"", "missing field"),
+            (
+                &CAMPUS.replace(
+                    "[detect]\nurl = 'login\\.campus\\.edu'\nbody = 'Campus Wi-Fi'\n",
+                    "[detect]\n",
+                ),
+                "no detect rules",
+            ),
+            (
+                &CAMPUS.replace("'Campus Wi-Fi'", "'Campus ('"),
+                "invalid pattern",
+            ),
+            (
+                &CAMPUS.replace("'You are now connected'", "'(connected'"),
+                "regex parse error",
+            ),
+            (
+                &CAMPUS.replace("form#login", "form[["),
+                "invalid form selector",
+            ),
+        ];
+        for (toml, expected) in cases {
+            let err = DeclarativeDriver::from_toml(toml).err().unwrap();
+            assert!(
+                format!("{:#}", err).contains(expected),
+                "{}: {:#}",
+                expected,
+                err
+            );
+        }
+        assert!(
+            DeclarativeDriver::from_toml(include_str!("../../../resources/portals/fortinet.toml"))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn detects_by_url_or_body() {
+        let driver = DeclarativeDriver::from_toml(CAMPUS).unwrap();
+        let cases = [
+            ("https://login.campus.edu/", "", true),
+            ("http://10.0.0.1/", "<h1>Campus Wi-Fi</h1>", true),
+            ("http://10.0.0.1/", "<h1>Hotel Wi-Fi</h1>", false),
+            ("http://login-campus.edu/", "", false),
+        ];
+        for (url, body, expected) in cases {
+            assert_eq!(
+                driver.detect(&login_page(url, body)),
+                expected,
+                "{} {}",
+                url,
+                body
+            );
+        }
+    }
+
+    #[test]
+    fn classifies_answers() {
+        let driver = DeclarativeDriver::from_toml(CAMPUS).unwrap();
+        let cases = [
+            ("You are now connected", Event::Success),
+            ("Invalid username or password", Event::WrongCreds),
+            ("Too many sessions", Event::MaxConcurrent),
+            // success wins over a stray error message elsewhere on the page
+            (
+                "You are now connected. Too many sessions? Log out elsewhere.",
+                Event::Success,
+            ),
+            ("Something went wrong", Event::Unknown),
+        ];
+        for (body, expected) in cases {
+            assert_eq!(driver.classify_response(body), expected, "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn needs_the_listed_hidden_fields() {
+        let base = test_server::serve(|path| match path {
+            "/auth" => page("<p>You are now connected</p>"),
+            _ => not_found(),
+        })
+        .await;
+        let driver = DeclarativeDriver::from_toml(CAMPUS).unwrap();
+        let http = HttpSession::new(Duration::from_secs(5));
+        let form = |token: &str| {
+            format!(
+                r#"<form id="login" action="/auth" method="post">{}<input name="user"><input type="password" name="pass"></form>"#,
+                token
+            )
+        };
+
+        let complete = login_page(
+            &base,
+            &form(r#"<input type="hidden" name="token" value="t1">"#),
+        );
+        let outcome = driver
+            .login(&http, &complete, &credentials())
+            .await
+            .unwrap();
+        assert_eq!(outcome.event, Event::Success);
+
+        let incomplete = login_page(&base, &form(""));
+        let err = driver
+            .login(&http, &incomplete, &credentials())
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("\"token\""), "{}", err);
+    }
+}
@@ -0,0 +1,69 @@
+use async_trait::async_trait;
+use regex::Regex;
+
+use super::declarative::DeclarativeDriver;
+use super::{LoginOutcome, LoginPage, PortalDriver, PortalSession};
+use crate::captive::error::CaptiveError;
+use crate::captive::session::HttpSession;
+use crate::configs::Profile;
+use crate::event::Event;
+
+const DEFINITION: &str = include_str!("../../../resources/portals/fortinet.toml");
+
+/// FortiGate `fgtauth` portal. The form and response handling comes from the
+/// bundled definition; this type is where FortiGate specific behaviour lives.
+pub struct FortinetDriver {
+    form: DeclarativeDriver,
+    keepalive_regex: Regex,
+    logout_regex: Regex,
+}
+
+impl FortinetDriver {
+    pub fn new() -> Self {
+        Self {
+            form: DeclarativeDriver::from_toml(DEFINITION)
+                .expect("bundled fortinet definition is invalid"),
+            keepalive_regex: Regex::new(r#"https?://[^/"'\s]+/keepalive\?[^"'\s<]+"#).unwrap(),
+            logout_regex: Regex::new(r#"https?://[^/"'\s]+/logout\?[^"'\s<]+"#).unwrap(),
+        }
+    }
+}
+
+#[async_trait]
+impl PortalDriver for FortinetDriver {
+    fn name(&self) -> &str {
+        "fortinet"
+    }
+
+    fn detect(&self, page: &LoginPage) -> bool {
+        self.form.detect(page)
+    }
+
+    async fn login(
+        &self,
+        http: &HttpSession,
+        page: &LoginPage,
+        profile: &Profile,
+    ) -> Result<LoginOutcome, CaptiveError> {
+        let Some((username, password)) = profile.credentials() else {
+            return Ok(Event::Unknown.into());
+        };
+        let body = self
+            .form
+            .submit(&http.client, page, username, password)
+            .await?;
+        let find = |re: &Regex| re.find(&body).map(|m| m.as_str().to_string());
+        Ok(LoginOutcome {
+            event: self.classify_response(&body),
+            session: PortalSession {
+                keepalive_url: find(&self.keepalive_regex),
+                logout_url: find(&self.logout_regex),
+                ..Default::default()
+            },
+        })
+    }
+
+    fn classify_response(&self, body: &str) -> Event {
+        self.form.classify_response(body)
+    }
+}
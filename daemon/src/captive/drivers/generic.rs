@@ -0,0 +1,133 @@
+use async_trait::async_trait;
+use log::{debug, warn};
+use regex::Regex;
+
+use super::{LoginOutcome, LoginPage, PortalDriver};
+use crate::captive::error::CaptiveError;
+use crate::captive::form::{self, LoginForm};
+use crate::captive::session::HttpSession;
+use crate::configs::Profile;
+use crate::event::Event;
+
+/// Fallback for portals no other driver knows: finds the login form and fills
+/// in the credentials. Only the login form coming back counts as a rejection,
+/// since wording that merely sounds like one turns up in many a success page;
+/// answers it can't judge are left to the probe after the login.
+pub struct GenericDriver {
+    max_concurrent_regex: Regex,
+}
+
+impl GenericDriver {
+    pub fn new() -> Self {
+        Self {
+            max_concurrent_regex: Regex::new(
+                r"(?i)concurrent (sessions|logins|users|devices)|already (logged|signed) in|maximum (number of )?(sessions|devices|users)|too many (sessions|devices)",
+            )
+            .unwrap(),
+        }
+    }
+}
+
+#[async_trait]
+impl PortalDriver for GenericDriver {
+    fn name(&self) -> &str {
+        "generic"
+    }
+
+    fn detect(&self, page: &LoginPage) -> bool {
+        LoginForm::find(&page.url, &page.body).is_some()
+    }
+
+    async fn login(
+        &self,
+        http: &HttpSession,
+        page: &LoginPage,
+        profile: &Profile,
+    ) -> Result<LoginOutcome, CaptiveError> {
+        let Some((username, password)) = profile.credentials() else {
+            return Ok(Event::Unknown.into());
+        };
+        let Some(mut form) = LoginForm::find(&page.url, &page.body) else {
+            return Err(CaptiveError::MissingForm(page.url.clone()));
+        };
+        if form.username_field.is_none() {
+            warn!(
+                "login form at {} has no recognisable username field",
+                page.url
+            );
+        }
+        form.fill_credentials(username, password);
+        debug!(
+            "submitting {:?} and {:?} to {}",
+            form.username_field, form.password_field, form.action
+        );
+        let res = form.submit(&http.client).await?;
+        if !res.status().is_success() {
+            return Err(CaptiveError::status(&form.action, res.status()));
+        }
+        Ok(self
+            .classify_response(&res.text().await.unwrap_or_default())
+            .into())
+    }
+
+    fn classify_response(&self, body: &str) -> Event {
+        if self
+            .max_concurrent_regex
+            .is_match(&form::visible_text(body))
+        {
+            Event::MaxConcurrent
+        } else if form::asks_for_password(body) {
+            // being handed the login form again is the usual way of saying no
+            Event::WrongCreds
+        } else {
+            Event::Unknown
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_answers() {
+        let driver = GenericDriver::new();
+        let cases = [
+            ("<h1>Welcome, you are now online</h1>", Event::Unknown),
+            ("<p>Redirecting...</p>", Event::Unknown),
+            // wording alone is left to the probe
+            ("<p>Invalid username or password</p>", Event::Unknown),
+            (
+                r#"<h1>Connected</h1><script>if (failed) { retry(); } // concurrent</script>"#,
+                Event::Unknown,
+            ),
+            (
+                "<style>.concurrent-sessions { display: none }</style><p>Online</p>",
+                Event::Unknown,
+            ),
+            (
+                r#"<form><input name="u"><input type="password" name="p"></form>"#,
+                Event::WrongCreds,
+            ),
+            (
+                r#"<FORM><INPUT TYPE="Password" NAME="p"></FORM>"#,
+                Event::WrongCreds,
+            ),
+            (
+                "<p>Maximum number of devices reached</p>",
+                Event::MaxConcurrent,
+            ),
+            (
+                "<p>You are already logged in elsewhere</p>",
+                Event::MaxConcurrent,
+            ),
+            (
+                "<p>Too many concurrent sessions for this account</p>",
+                Event::MaxConcurrent,
+            ),
+        ];
+        for (body, expected) in cases {
+            assert_eq!(driver.classify_response(body), expected, "{}", body);
+        }
+    }
+}
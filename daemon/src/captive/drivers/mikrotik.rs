@@ -0,0 +1,255 @@
+use async_trait::async_trait;
+use log::{debug, warn};
+use md5::{Digest, Md5};
+use regex::Regex;
+use reqwest::Client;
+use reqwest::Url;
+use scraper::Selector;
+
+use super::{LoginOutcome, LoginPage, PortalDriver, PortalSession};
+use crate::captive::error::CaptiveError;
+use crate::captive::form::LoginForm;
+use crate::captive::session::HttpSession;
+use crate::configs::Profile;
+use crate::event::Event;
+
+/// MikroTik Hotspot. With CHAP enabled its login page posts
+/// `md5(chap-id + password + chap-challenge)` from a hidden `sendin` form
+/// instead of the password itself.
+pub struct MikroTikDriver {
+    chap_regex: Regex,
+    detect_regex: Regex,
+    logged_in_regex: Regex,
+    wrong_creds_regex: Regex,
+    max_concurrent_regex: Regex,
+}
+
+impl MikroTikDriver {
+    pub fn new() -> Self {
+        Self {
+            // hexMD5('\011' + document.login.password.value + '\273\046...')
+            chap_regex: Regex::new(
+                r"hexMD5\(\s*'(?P<id>[^']*)'\s*\+\s*document\.login\.password\.value\s*\+\s*'(?P<challenge>[^']*)'\s*\)",
+            )
+            .unwrap(),
+            detect_regex: Regex::new(r#"(?i)mikrotik|name="sendin"|hexMD5\("#).unwrap(),
+            logged_in_regex: Regex::new(r"(?i)you are logged in|/logout|log ?off").unwrap(),
+            wrong_creds_regex: Regex::new(
+                r"(?i)invalid username or password|invalid password|no valid profile found|user .* not found",
+            )
+            .unwrap(),
+            max_concurrent_regex: Regex::new(
+                r"(?i)no more sessions are allowed|simultaneous session limit",
+            )
+            .unwrap(),
+        }
+    }
+
+    /// `chap-id` and `chap-challenge` as bytes, if the page uses CHAP.
+    fn challenge(&self, body: &str) -> Option<(Vec<u8>, Vec<u8>)> {
+        let caps = self.chap_regex.captures(body)?;
+        Some((
+            unescape_octal(&caps["id"]),
+            unescape_octal(&caps["challenge"]),
+        ))
+    }
+
+    /// Whether the `/status` page says we are logged in.
+    async fn logged_in(&self, client: &Client, page: &Url) -> Result<bool, CaptiveError> {
+        let url = page
+            .join("/status")
+            .map_err(|e| CaptiveError::unparseable(page, e))?;
+        let resp = client.get(url).send().await?;
+        if !resp.status().is_success() {
+            return Ok(false);
+        }
+        let body = resp.text().await.unwrap_or_default();
+        Ok(matches!(self.classify_response(&body), Event::Success))
+    }
+}
+
+#[async_trait]
+impl PortalDriver for MikroTikDriver {
+    fn name(&self) -> &str {
+        "mikrotik"
+    }
+
+    fn detect(&self, page: &LoginPage) -> bool {
+        self.detect_regex.is_match(&page.body)
+            && LoginForm::select(&page.url, &page.body, &Selector::parse("form").unwrap()).is_some()
+    }
+
+    async fn login(
+        &self,
+        http: &HttpSession,
+        page: &LoginPage,
+        profile: &Profile,
+    ) -> Result<LoginOutcome, CaptiveError> {
+        let client = &http.client;
+        let Some((username, password)) = profile.credentials() else {
+            return Ok(Event::Unknown.into());
+        };
+        let sendin = Selector::parse(r#"form[name="sendin"]"#).unwrap();
+        let login = Selector::parse(r#"form[name="login"], form"#).unwrap();
+        let form = match (
+            self.challenge(&page.body),
+            LoginForm::select(&page.url, &page.body, &sendin),
+        ) {
+            (Some((id, challenge)), Some(mut form)) => {
+                debug!("hotspot uses CHAP, hashing the password");
+                form.set("username", username);
+                form.set("password", &chap_response(&id, password, &challenge));
+                form
+            }
+            _ => {
+                let Some(mut form) = LoginForm::select(&page.url, &page.body, &login) else {
+                    return Err(CaptiveError::MissingForm(page.url.clone()));
+                };
+                warn!("hotspot at {} sends the password in plain text", page.url);
+                form.set("username", username);
+                form.set("password", password);
+                form
+            }
+        };
+        debug!("submitting hotspot login to {}", form.action);
+        let res = form.submit(client).await?;
+        if !res.status().is_success() {
+            return Err(CaptiveError::status(&form.action, res.status()));
+        }
+        let event = match self.classify_response(&res.text().await.unwrap_or_default()) {
+            // alogin.html only meta-refreshes to the status page
+            Event::Unknown if self.logged_in(client, &form.action).await? => Event::Success,
+            event => event,
+        };
+        let url = |path: &str| form.action.join(path).ok().map(|url| url.to_string());
+        Ok(LoginOutcome {
+            event,
+            session: PortalSession {
+                keepalive_url: url("/status"),
+                logout_url: url("/logout"),
+                ..Default::default()
+            },
+        })
+    }
+
+    /// The status page has a logout form, so it can't be told from the login
+    /// page by `detect`.
+    async fn keepalive(
+        &self,
+        client: &Client,
+        session: &PortalSession,
+    ) -> Result<Event, CaptiveError> {
+        let Some(url) = session
+            .keepalive_url
+            .as_ref()
+            .and_then(|url| Url::parse(url).ok())
+        else {
+            return Ok(Event::SessionExpired);
+        };
+        if self.logged_in(client, &url).await? {
+            Ok(Event::KeepAlive)
+        } else {
+            Ok(Event::SessionExpired)
+        }
+    }
+
+    fn classify_response(&self, body: &str) -> Event {
+        if self.max_concurrent_regex.is_match(body) {
+            Event::MaxConcurrent
+        } else if self.wrong_creds_regex.is_match(body) {
+            Event::WrongCreds
+        } else if self.logged_in_regex.is_match(body) {
+            Event::Success
+        } else if self.chap_regex.is_match(body) {
+            // the login page again without a reason
+            Event::WrongCreds
+        } else {
+            Event::Unknown
+        }
+    }
+}
+
+/// The hex MD5 of `id + password + challenge`, as the hotspot's `md5.js`
+/// computes it.
+fn chap_response(id: &[u8], password: &str, challenge: &[u8]) -> String {
+    let mut hasher = Md5::new();
+    hasher.update(id);
+    hasher.update(password.as_bytes());
+    hasher.update(challenge);
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Turns the JavaScript octal escapes MikroTik renders `$(chap-id)` and
+/// `$(chap-challenge)` as, `'\011\273...'`, into bytes.
+fn unescape_octal(escaped: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut chars = escaped.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        let mut value: u32 = 0;
+        let mut digits = 0;
+        while digits < 3
+            && let Some(digit) = chars.peek().and_then(|d| d.to_digit(8))
+        {
+            value = value * 8 + digit;
+            digits += 1;
+            chars.next();
+        }
+        match digits {
+            0 => {
+                if let Some(next) = chars.next() {
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(next.encode_utf8(&mut buf).as_bytes());
+                }
+            }
+            _ => bytes.push(value as u8),
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOGIN_JS: &str = r"document.sendin.password.value = hexMD5('\011' + document.login.password.value + '\273\046\310\145\001\322\077\236\251\102\117\230\324\017\145\047');";
+
+    #[test]
+    fn unescapes_octal() {
+        let cases: [(&str, &[u8]); 5] = [
+            (r"\011", &[0o11]),
+            (r"\273\046\310", &[0xbb, 0x26, 0xc8]),
+            (r"\1\12x", &[1, 0o12, b'x']),
+            (r"\0101", &[0o10, b'1']),
+            (r"a\\b\'", b"a\\b'"),
+        ];
+        for (escaped, expected) in cases {
+            assert_eq!(unescape_octal(escaped), expected, "{}", escaped);
+        }
+    }
+
+    #[test]
+    fn answers_the_challenge() {
+        let (id, challenge) = MikroTikDriver::new().challenge(LOGIN_JS).unwrap();
+        assert_eq!(id, [0x09]);
+        assert_eq!(
+            challenge,
+            [
+                0xbb, 0x26, 0xc8, 0x65, 0x01, 0xd2, 0x3f, 0x9e, 0xa9, 0x42, 0x4f, 0x98, 0xd4, 0x0f,
+                0x65, 0x27
+            ]
+        );
+        assert_eq!(
+            chap_response(&id, "secret", &challenge),
+            "c46b6d7025abf4aee4c68aa824a74442"
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use async_trait::async_trait;
+use log::{debug, error, warn};
+use reqwest::header::HeaderMap;
+use reqwest::{Client, Response, Url};
+use std::path::Path;
+use std::time::Instant;
+
+use super::error::CaptiveError;
+use super::session::HttpSession;
+use crate::configs::Profile;
+use crate::event::Event;
+
+pub mod click_through;
+pub mod coova;
+pub mod declarative;
+pub mod fortinet;
+pub mod generic;
+pub mod mikrotik;
+pub mod pfsense;
+
+/// The page a captive probe redirected us to, as fetched right before login.
+pub struct LoginPage {
+    pub url: String,
+    pub headers: HeaderMap,
+    pub body: String,
+    /// Every URL visited on the way to `url`, ending with `url` itself.
+    pub chain: Vec<String>,
+}
+
+impl LoginPage {
+    /// The page a response landed on, as if we had navigated straight to it.
+    pub async fn from_response(resp: Response) -> Self {
+        let url = resp.url().to_string();
+        let headers = resp.headers().clone();
+        Self {
+            chain: vec![url.clone()],
+            url,
+            headers,
+            body: resp.text().await.unwrap_or_default(),
+        }
+    }
+
+    /// Hosts along the redirect chain, without repeats.
+    pub fn hosts(&self) -> Vec<String> {
+        let mut hosts: Vec<String> = Vec::new();
+        for url in self.chain.iter().filter_map(|url| Url::parse(url).ok()) {
+            if let Some(host) = url.host_str()
+                && !hosts.iter().any(|h| h == host)
+            {
+                hosts.push(host.to_string());
+            }
+        }
+        hosts
+    }
+}
+
+/// What a driver learned about the portal session it just opened.
+#[derive(Debug, Clone, Default)]
+pub struct PortalSession {
+    pub keepalive_url: Option<String>,
+    pub logout_url: Option<String>,
+    /// When the portal said the session ends.
+    pub expires: Option<Instant>,
+}
+
+pub struct LoginOutcome {
+    pub event: Event,
+    pub session: PortalSession,
+}
+
+impl From<Event> for LoginOutcome {
+    fn from(event: Event) -> Self {
+        Self {
+            event,
+            session: PortalSession::default(),
+        }
+    }
+}
+
+/// Vendor specific knowledge about a captive portal.
+///
+/// `Captive` asks every registered driver whether it recognises the login
+/// page and hands the login over to the first one that does.
+#[async_trait]
+pub trait PortalDriver: Send + Sync {
+    fn name(&self) -> &str;
+    /// Whether the driver can log in with `profile` at all.
+    fn supports(&self, profile: &Profile) -> bool {
+        profile.credentials().is_some()
+    }
+    fn detect(&self, page: &LoginPage) -> bool;
+    async fn login(
+        &self,
+        http: &HttpSession,
+        page: &LoginPage,
+        profile: &Profile,
+    ) -> Result<LoginOutcome, CaptiveError>;
+    fn classify_response(&self, body: &str) -> Event;
+
+    async fn logout(
+        &self,
+        client: &Client,
+        session: &PortalSession,
+    ) -> Result<Event, CaptiveError> {
+        let Some(url) = &session.logout_url else {
+            warn!("{} driver has no logout URL for this session", self.name());
+            return Ok(Event::LogoutFailed);
+        };
+        let resp = client.get(url).send().await?;
+        if !resp.status().is_success() {
+            return Err(CaptiveError::status(url, resp.status()));
+        }
+        Ok(Event::LoggedOut)
+    }
+
+    /// Refreshes the session. A keepalive that lands back on a login page
+    /// this driver recognises means the portal has already dropped us.
+    async fn keepalive(
+        &self,
+        client: &Client,
+        session: &PortalSession,
+    ) -> Result<Event, CaptiveError> {
+        let Some(url) = &session.keepalive_url else {
+            return Ok(Event::SessionExpired);
+        };
+        let resp = client.get(url).send().await?;
+        if !resp.status().is_success() {
+            error!("keepalive returned {}", resp.status());
+            return Ok(Event::SessionExpired);
+        }
+        let page = LoginPage::from_response(resp).await;
+        if self.detect(&page) {
+            debug!("keepalive redirected to the login page {}", page.url);
+            return Ok(Event::SessionExpired);
+        }
+        Ok(Event::KeepAlive)
+    }
+}
+
+/// Definitions found in `portals_dir` come first so they can take over a
+/// portal that a built-in driver would otherwise claim. The generic driver
+/// goes last, it claims any page with a login form.
+pub fn load(portals_dir: &Path) -> Vec<Box<dyn PortalDriver>> {
+    let mut drivers = declarative::load_dir(portals_dir);
+    drivers.push(Box::new(fortinet::FortinetDriver::new()));
+    drivers.push(Box::new(mikrotik::MikroTikDriver::new()));
+    drivers.push(Box::new(coova::CoovaDriver::new()));
+    drivers.push(Box::new(pfsense::PfSenseDriver::new()));
+    drivers.push(Box::new(generic::GenericDriver::new()));
+    drivers.push(Box::new(click_through::ClickThroughDriver::new()));
+    drivers
+}
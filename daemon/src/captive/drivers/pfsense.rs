@@ -0,0 +1,403 @@
+use async_trait::async_trait;
+use log::{debug, warn};
+use regex::Regex;
+use reqwest::Client;
+use reqwest::Url;
+use scraper::{Html, Selector};
+use serde_json::Value;
+
+use super::{LoginOutcome, LoginPage, PortalDriver, PortalSession};
+use crate::captive::error::CaptiveError;
+use crate::captive::form::LoginForm;
+use crate::captive::session::HttpSession;
+use crate::configs::Profile;
+use crate::event::Event;
+
+/// pfSense and OPNsense captive portals.
+///
+/// pfSense serves a plain form posting `auth_user`/`auth_pass` or
+/// `auth_voucher` with the `zone` and `redirurl`, and answers with its login
+/// page again on failure. On success it redirects to `redirurl` or, with the
+/// logout popup enabled, shows a form posting `logout_id` back to the portal.
+///
+/// OPNsense's page logs on from JavaScript through
+/// `/api/captiveportal/access/logon/<zone>/`, which answers in JSON.
+pub struct PfSenseDriver {
+    opnsense_api_regex: Regex,
+    logout_id_regex: Regex,
+    wrong_creds_regex: Regex,
+    max_concurrent_regex: Regex,
+}
+
+impl PfSenseDriver {
+    pub fn new() -> Self {
+        Self {
+            opnsense_api_regex: Regex::new(r"/api/captiveportal/access/logon/(?P<zone>\d+)/")
+                .unwrap(),
+            logout_id_regex: Regex::new(
+                r#"name=\\?"logout_id\\?"[^>]*value=\\?"(?P<id>[^"\\]+)"#,
+            )
+            .unwrap(),
+            wrong_creds_regex: Regex::new(
+                r"(?i)invalid credentials|voucher\(s\) .*(invalid|expired|used)|authentication failed",
+            )
+            .unwrap(),
+            max_concurrent_regex: Regex::new(
+                r"(?i)already authenticated using another mac|concurrent",
+            )
+            .unwrap(),
+        }
+    }
+
+    /// The OPNsense zone id, if `body` is an OPNsense login page.
+    fn opnsense_zone(&self, body: &str) -> Option<String> {
+        self.opnsense_api_regex
+            .captures(body)
+            .map(|caps| caps["zone"].to_string())
+    }
+
+    fn login_form(&self, page: &LoginPage) -> Option<LoginForm> {
+        let selector = Selector::parse(
+            r#"form:has(input[name="zone"]):has(input[name="auth_user"], input[name="auth_voucher"])"#,
+        )
+        .unwrap();
+        LoginForm::select(&page.url, &page.body, &selector)
+    }
+
+    /// Where the logout popup posts to, with its fields as the query.
+    fn logout_url(&self, page_url: &Url, body: &str) -> Option<String> {
+        let id = &self.logout_id_regex.captures(body)?["id"];
+        let mut url = page_url.clone();
+        url.query_pairs_mut().append_pair("logout_id", id);
+        Some(url.to_string())
+    }
+
+    async fn pfsense_login(
+        &self,
+        client: &Client,
+        page: &LoginPage,
+        profile: &Profile,
+    ) -> Result<LoginOutcome, CaptiveError> {
+        let Some(mut form) = self.login_form(page) else {
+            return Err(CaptiveError::MissingForm(page.url.clone()));
+        };
+        match (profile, profile.credentials()) {
+            (_, Some((username, password))) if form.get("auth_user").is_some() => {
+                form.set("auth_user", username);
+                form.set("auth_pass", password);
+            }
+            (Profile::Voucher { voucher }, _) if form.get("auth_voucher").is_some() => {
+                form.set("auth_voucher", voucher);
+            }
+            _ => {
+                warn!(
+                    "the portal at {} doesn't take this kind of profile",
+                    page.url
+                );
+                return Ok(Event::Unknown.into());
+            }
+        }
+        debug!("submitting zone {:?} to {}", form.get("zone"), form.action);
+        let redirurl = form.get("redirurl").and_then(|url| Url::parse(url).ok());
+        let res = form.submit(client).await?;
+        if !res.status().is_success() {
+            return Err(CaptiveError::status(&form.action, res.status()));
+        }
+        // pfSense sends the browser on to `redirurl` once it let it through
+        let passed = redirurl.as_ref() == Some(res.url());
+        let body = res.text().await.unwrap_or_default();
+        let event = match self.classify_response(&body) {
+            Event::Unknown if passed => Event::Success,
+            event => event,
+        };
+        let logout_url = self.logout_url(&form.action, &body);
+        if logout_url.is_none() && matches!(event, Event::Success) {
+            debug!("no logout popup, the session can't be logged out of");
+        }
+        Ok(LoginOutcome {
+            event,
+            session: PortalSession {
+                logout_url,
+                ..Default::default()
+            },
+        })
+    }
+
+    async fn opnsense_login(
+        &self,
+        client: &Client,
+        page: &LoginPage,
+        zone: &str,
+        profile: &Profile,
+    ) -> Result<LoginOutcome, CaptiveError> {
+        let Some((username, password)) = profile.credentials() else {
+            return Ok(Event::Unknown.into());
+        };
+        let base = Url::parse(&page.url).map_err(|e| CaptiveError::unparseable(&page.url, e))?;
+        let api = |action: &str| {
+            base.join(&format!("/api/captiveportal/access/{}/{}/", action, zone))
+                .unwrap()
+        };
+        let status: Value = client
+            .post(api("logon"))
+            .form(&[("user", username), ("password", password)])
+            .send()
+            .await?
+            .json()
+            .await?;
+        debug!("captive portal API answered {}", status);
+        Ok(LoginOutcome {
+            event: self.classify_response(&status.to_string()),
+            session: PortalSession {
+                keepalive_url: Some(api("status").to_string()),
+                logout_url: Some(api("logoff").to_string()),
+                ..Default::default()
+            },
+        })
+    }
+}
+
+#[async_trait]
+impl PortalDriver for PfSenseDriver {
+    fn name(&self) -> &str {
+        "pfsense"
+    }
+
+    fn supports(&self, profile: &Profile) -> bool {
+        matches!(
+            profile,
+            Profile::Credentials { .. } | Profile::Voucher { .. }
+        )
+    }
+
+    fn detect(&self, page: &LoginPage) -> bool {
+        self.opnsense_zone(&page.body).is_some() || self.login_form(page).is_some()
+    }
+
+    async fn login(
+        &self,
+        http: &HttpSession,
+        page: &LoginPage,
+        profile: &Profile,
+    ) -> Result<LoginOutcome, CaptiveError> {
+        let client = &http.client;
+        match self.opnsense_zone(&page.body) {
+            Some(zone) => self.opnsense_login(client, page, &zone, profile).await,
+            None => self.pfsense_login(client, page, profile).await,
+        }
+    }
+
+    /// pfSense's logout popup posts its fields rather than linking to a URL,
+    /// and OPNsense's API only takes POSTs.
+    async fn logout(
+        &self,
+        client: &Client,
+        session: &PortalSession,
+    ) -> Result<Event, CaptiveError> {
+        let Some(mut url) = session
+            .logout_url
+            .as_ref()
+            .and_then(|url| Url::parse(url).ok())
+        else {
+            warn!("pfsense driver has no logout URL for this session");
+            return Ok(Event::LogoutFailed);
+        };
+        let mut fields: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        if fields.iter().any(|(name, _)| name == "logout_id") {
+            url.set_query(None);
+            fields.push(("logout".to_string(), "Logout".to_string()));
+        } else {
+            fields.clear();
+        }
+        let resp = client.post(url.clone()).form(&fields).send().await?;
+        if !resp.status().is_success() {
+            return Err(CaptiveError::status(url, resp.status()));
+        }
+        Ok(Event::LoggedOut)
+    }
+
+    /// Only OPNsense has a status to poll.
+    async fn keepalive(
+        &self,
+        client: &Client,
+        session: &PortalSession,
+    ) -> Result<Event, CaptiveError> {
+        let Some(url) = &session.keepalive_url else {
+            return Ok(Event::SessionExpired);
+        };
+        let status: Value = client.post(url).send().await?.json().await?;
+        if status["clientState"] == "AUTHORIZED" {
+            Ok(Event::KeepAlive)
+        } else {
+            Ok(Event::SessionExpired)
+        }
+    }
+
+    fn classify_response(&self, body: &str) -> Event {
+        // OPNsense answers the logon with its client status
+        if let Ok(status) = serde_json::from_str::<Value>(body) {
+            return match status["clientState"].as_str() {
+                Some("AUTHORIZED") => Event::Success,
+                Some(_) => Event::WrongCreds,
+                None => Event::Unknown,
+            };
+        }
+        let doc = Html::parse_document(body);
+        let login_sel =
+            Selector::parse(r#"input[name="auth_user"], input[name="auth_voucher"]"#).unwrap();
+        if self.max_concurrent_regex.is_match(body) {
+            Event::MaxConcurrent
+        } else if self.wrong_creds_regex.is_match(body) || doc.select(&login_sel).next().is_some() {
+            // the login page again, with or without a reason
+            Event::WrongCreds
+        } else if self.logout_id_regex.is_match(body) {
+            Event::Success
+        } else {
+            // anything else may as well be an error page we don't know, which
+            // the probe after the login tells apart
+            Event::Unknown
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::captive::test_server::{self, not_found, page, redirect};
+    use std::time::Duration;
+
+    fn fixture(name: &str) -> LoginPage {
+        let body = match name {
+            "pfsense_login" => include_str!("../../../tests/fixtures/pfsense/pfsense_login.html"),
+            "pfsense_voucher" => {
+                include_str!("../../../tests/fixtures/pfsense/pfsense_voucher.html")
+            }
+            "pfsense_error" => include_str!("../../../tests/fixtures/pfsense/pfsense_error.html"),
+            "pfsense_voucher_expired" => {
+                include_str!("../../../tests/fixtures/pfsense/pfsense_voucher_expired.html")
+            }
+            "pfsense_concurrent" => {
+                include_str!("../../../tests/fixtures/pfsense/pfsense_concurrent.html")
+            }
+            "pfsense_logout_popup" => {
+                include_str!("../../../tests/fixtures/pfsense/pfsense_logout_popup.html")
+            }
+            "opnsense_login" => {
+                include_str!("../../../tests/fixtures/pfsense/opnsense_login.html")
+            }
+            _ => unreachable!("no fixture {}", name),
+        };
+        let url = "http://10.10.0.1:8002/index.php?zone=branch".to_string();
+        LoginPage {
+            chain: vec![url.clone()],
+            url,
+            headers: Default::default(),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn detects_pfsense_and_opnsense() {
+        let driver = PfSenseDriver::new();
+        assert!(driver.detect(&fixture("pfsense_login")));
+        assert!(driver.detect(&fixture("pfsense_voucher")));
+        assert!(driver.detect(&fixture("opnsense_login")));
+        assert!(!driver.detect(&fixture("pfsense_logout_popup")));
+        assert_eq!(
+            driver.opnsense_zone(&fixture("opnsense_login").body),
+            Some("2".to_string())
+        );
+    }
+
+    #[test]
+    fn keeps_zone_and_redirurl() {
+        let form = PfSenseDriver::new()
+            .login_form(&fixture("pfsense_login"))
+            .unwrap();
+        assert_eq!(form.get("zone"), Some("branch"));
+        assert_eq!(
+            form.get("redirurl"),
+            Some("http://www.msftconnecttest.com/redirect")
+        );
+        assert_eq!(form.get("accept"), Some("Continue"));
+        assert_eq!(
+            form.action.as_str(),
+            "http://10.10.0.1:8002/index.php?zone=branch"
+        );
+    }
+
+    #[test]
+    fn classifies_error_pages() {
+        let driver = PfSenseDriver::new();
+        let classify = |name: &str| driver.classify_response(&fixture(name).body);
+        assert!(matches!(classify("pfsense_error"), Event::WrongCreds));
+        assert!(matches!(
+            classify("pfsense_voucher_expired"),
+            Event::WrongCreds
+        ));
+        assert!(matches!(
+            classify("pfsense_concurrent"),
+            Event::MaxConcurrent
+        ));
+        assert!(matches!(classify("pfsense_logout_popup"), Event::Success));
+        // nothing says we are through
+        assert!(matches!(classify("pfsense_voucher"), Event::WrongCreds));
+        assert!(matches!(
+            driver.classify_response("<h1>502 Bad Gateway</h1><p>Proxy error</p>"),
+            Event::Unknown
+        ));
+        assert!(matches!(driver.classify_response(""), Event::Unknown));
+    }
+
+    #[tokio::test]
+    async fn follows_the_redirect_to_redirurl() {
+        let base = test_server::serve(|path| match path {
+            "/index.php?zone=branch" => redirect("/redirect"),
+            "/redirect" => page("<p>Microsoft Connect Test</p>"),
+            _ => not_found(),
+        })
+        .await;
+        let mut login = fixture("pfsense_login");
+        login.body = login
+            .body
+            .replace("http://10.10.0.1:8002", &base)
+            .replace("http://www.msftconnecttest.com", &base);
+        let profile = toml::from_str("rollno = \"jdoe\"\npassword = \"secret\"").unwrap();
+        let http = HttpSession::new(Duration::from_secs(5));
+        let outcome = PfSenseDriver::new()
+            .login(&http, &login, &profile)
+            .await
+            .unwrap();
+        assert!(matches!(outcome.event, Event::Success));
+        assert_eq!(outcome.session.logout_url, None);
+    }
+
+    #[test]
+    fn classifies_opnsense_answers() {
+        let driver = PfSenseDriver::new();
+        assert!(matches!(
+            driver.classify_response(r#"{"clientState":"AUTHORIZED","sessionId":"abc"}"#),
+            Event::Success
+        ));
+        assert!(matches!(
+            driver.classify_response(r#"{"clientState":"NOT_AUTHORIZED"}"#),
+            Event::WrongCreds
+        ));
+    }
+
+    #[test]
+    fn finds_the_logout_popup() {
+        let driver = PfSenseDriver::new();
+        let action = Url::parse("http://10.10.0.1:8002/index.php?zone=branch").unwrap();
+        assert_eq!(
+            driver
+                .logout_url(&action, &fixture("pfsense_logout_popup").body)
+                .as_deref(),
+            Some("http://10.10.0.1:8002/index.php?zone=branch&logout_id=5f2a9c1e7b3d4f60")
+        );
+        assert_eq!(
+            driver.logout_url(&action, &fixture("pfsense_error").body),
+            None
+        );
+    }
+}
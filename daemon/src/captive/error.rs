@@ -0,0 +1,93 @@
+use reqwest::StatusCode;
+use std::error::Error as _;
+use thiserror::Error;
+
+use crate::event::Event;
+
+/// Why talking to a portal failed.
+#[derive(Debug, Error)]
+pub enum CaptiveError {
+    #[error("timed out talking to {0}")]
+    Timeout(String),
+    #[error("TLS with {url} failed: {reason}")]
+    Tls { url: String, reason: String },
+    #[error("could not resolve the host of {0}")]
+    Dns(String),
+    #[error("could not connect to {url}: {reason}")]
+    Connection { url: String, reason: String },
+    #[error("{url} returned {status}")]
+    UnexpectedStatus { url: String, status: StatusCode },
+    #[error("could not make sense of {url}: {reason}")]
+    UnparseablePage { url: String, reason: String },
+    #[error("no login form on {0}")]
+    MissingForm(String),
+}
+
+impl CaptiveError {
+    pub fn unparseable(url: impl ToString, reason: impl ToString) -> Self {
+        Self::UnparseablePage {
+            url: url.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    pub fn status(url: impl ToString, status: StatusCode) -> Self {
+        Self::UnexpectedStatus {
+            url: url.to_string(),
+            status,
+        }
+    }
+}
+
+impl From<reqwest::Error> for CaptiveError {
+    fn from(e: reqwest::Error) -> Self {
+        let url = e
+            .url()
+            .map(|url| url.to_string())
+            .unwrap_or_else(|| String::from("the portal"));
+        if e.is_timeout() {
+            return Self::Timeout(url);
+        }
+        if let Some(status) = e.status() {
+            return Self::UnexpectedStatus { url, status };
+        }
+        if e.is_decode() {
+            return Self::UnparseablePage {
+                url,
+                reason: e.to_string(),
+            };
+        }
+        // reqwest doesn't type connection errors, the cause is in the message
+        let mut reason = e.to_string();
+        let mut source = e.source();
+        while let Some(cause) = source {
+            reason = cause.to_string();
+            source = cause.source();
+        }
+        let lower = reason.to_ascii_lowercase();
+        if lower.contains("dns error") || lower.contains("failed to lookup address") {
+            Self::Dns(url)
+        } else if lower.contains("certificate")
+            || lower.contains("tls")
+            || lower.contains("handshake")
+        {
+            Self::Tls { url, reason }
+        } else {
+            Self::Connection { url, reason }
+        }
+    }
+}
+
+impl From<&CaptiveError> for Event {
+    fn from(e: &CaptiveError) -> Self {
+        match e {
+            CaptiveError::Timeout(_) => Event::Timeout,
+            CaptiveError::Tls { .. } => Event::TlsError,
+            CaptiveError::Dns(_) => Event::DnsError,
+            CaptiveError::Connection { .. } => Event::ConnectionFailed,
+            CaptiveError::UnexpectedStatus { .. } => Event::UnexpectedStatus,
+            CaptiveError::UnparseablePage { .. } => Event::UnparseablePage,
+            CaptiveError::MissingForm(_) => Event::MissingForm,
+        }
+    }
+}
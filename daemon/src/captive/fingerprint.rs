@@ -0,0 +1,412 @@
+use log::debug;
+use regex::Regex;
+use reqwest::{Client, Url};
+use serde::Serialize;
+use std::fmt;
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+use super::drivers::LoginPage;
+
+/// Passive signals adding up to less than this are backed up by asking the
+/// portal for the paths its vendor is known to serve.
+const ACTIVE_CHECK_BELOW: u8 = 50;
+/// The fingerprint is only logged, so the login doesn't wait long for it.
+const ACTIVE_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Vendor {
+    FortiGate,
+    CiscoIse,
+    CiscoWlc,
+    ArubaClearPass,
+    PfSense,
+    OpnSense,
+    MikroTik,
+    UniFi,
+    CoovaChilli,
+    OpenNds,
+}
+
+impl fmt::Display for Vendor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Vendor::FortiGate => "FortiGate",
+            Vendor::CiscoIse => "Cisco ISE",
+            Vendor::CiscoWlc => "Cisco WLC",
+            Vendor::ArubaClearPass => "Aruba ClearPass",
+            Vendor::PfSense => "pfSense",
+            Vendor::OpnSense => "OPNsense",
+            Vendor::MikroTik => "MikroTik Hotspot",
+            Vendor::UniFi => "UniFi",
+            Vendor::CoovaChilli => "CoovaChilli",
+            Vendor::OpenNds => "openNDS",
+        })
+    }
+}
+
+/// The vendor a portal most looks like, with a confidence out of 100.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Fingerprint {
+    pub vendor: Vendor,
+    pub confidence: u8,
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}% confidence)", self.vendor, self.confidence)
+    }
+}
+
+enum Signal {
+    /// Any URL on the way to the login page.
+    Url(&'static str),
+    Body(&'static str),
+    Header(&'static str, &'static str),
+}
+
+struct Rule {
+    vendor: Vendor,
+    signal: Signal,
+    weight: u8,
+    regex: Regex,
+}
+
+static RULES: LazyLock<Vec<Rule>> = LazyLock::new(|| {
+    use Signal::*;
+    use Vendor::*;
+    [
+        (FortiGate, Url(r"/fgtauth\?"), 60),
+        (FortiGate, Url(r":100[03]/"), 10),
+        (FortiGate, Body(r#"name="magic""#), 30),
+        (FortiGate, Body(r"(?i)fortigate|fortinet"), 20),
+        (
+            CiscoIse,
+            Url(r"/portal/(PortalSetup\.action|gateway\?)"),
+            60,
+        ),
+        (CiscoIse, Url(r":8443/portal/"), 20),
+        (
+            CiscoIse,
+            Body(r"(?i)cisco identity services engine|guestportal"),
+            30,
+        ),
+        (CiscoWlc, Url(r"switch_url=|/fs/customwebauth/"), 60),
+        (CiscoWlc, Body(r#"name="buttonClicked""#), 30),
+        (CiscoWlc, Body(r"(?i)cisco systems"), 10),
+        (
+            ArubaClearPass,
+            Url(r"/guest/[^/?]+\.php|/cgi-bin/login\?cmd=login"),
+            50,
+        ),
+        (ArubaClearPass, Url(r"[?&](switchip|apname|essid)="), 20),
+        (ArubaClearPass, Body(r"(?i)clearpass|aruba networks"), 30),
+        (PfSense, Url(r":800[23]/"), 20),
+        (PfSense, Body(r"(?i)pfsense"), 40),
+        (PfSense, Body(r#"name="zone""#), 30),
+        (PfSense, Body(r#"name="auth_voucher""#), 20),
+        (OpnSense, Url(r":8000/"), 20),
+        (OpnSense, Body(r"(?i)opnsense"), 40),
+        (OpnSense, Body(r"/api/captiveportal/access/logon"), 50),
+        (MikroTik, Url(r"/login\?dst="), 20),
+        (MikroTik, Body(r"chap-challenge|chap-id|hexMD5\("), 50),
+        (MikroTik, Body(r"(?i)mikrotik"), 30),
+        (MikroTik, Header("server", r"(?i)mikrotik"), 40),
+        (UniFi, Url(r"/guest/s/[^/]+/"), 60),
+        (UniFi, Url(r":88(80|43)/"), 20),
+        (UniFi, Body(r"(?i)unifi|ubiquiti"), 30),
+        (CoovaChilli, Url(r"[?&]uamip="), 40),
+        (CoovaChilli, Url(r"[?&]uamport="), 20),
+        (CoovaChilli, Url(r"[?&]challenge="), 20),
+        (CoovaChilli, Body(r"(?i)coova|chillispot|chilli\.js"), 30),
+        (OpenNds, Url(r"/opennds_preauth/|[?&]fas="), 50),
+        (OpenNds, Url(r":2050/"), 20),
+        (OpenNds, Body(r"(?i)opennds|nodogsplash"), 40),
+        (OpenNds, Header("server", r"(?i)opennds|nodogsplash"), 40),
+    ]
+    .into_iter()
+    .map(|(vendor, signal, weight)| {
+        let pattern = match signal {
+            Url(re) | Body(re) | Header(_, re) => re,
+        };
+        Rule {
+            vendor,
+            signal,
+            weight,
+            regex: Regex::new(pattern).unwrap(),
+        }
+    })
+    .collect()
+});
+
+/// Paths only one vendor answers on, and what the answer contains.
+static WELL_KNOWN_PATHS: LazyLock<Vec<(Vendor, &str, Regex)>> = LazyLock::new(|| {
+    [
+        (Vendor::CoovaChilli, "/json/status", "clientState"),
+        (
+            Vendor::OpnSense,
+            "/api/captiveportal/access/status",
+            "clientState",
+        ),
+        // the status page's logout form, or the login page for a stranger
+        (
+            Vendor::MikroTik,
+            "/status",
+            r#"action="[^"]*/logout"\s+name="logout"|name="sendin""#,
+        ),
+    ]
+    .into_iter()
+    .map(|(vendor, path, pattern)| (vendor, path, Regex::new(pattern).unwrap()))
+    .collect()
+});
+
+/// Guesses the portal vendor from the login page, its headers and the URLs
+/// that led to it. When that isn't conclusive, `client` asks the portal for
+/// well-known vendor paths, all at once and briefly.
+pub async fn identify(client: &Client, page: &LoginPage) -> Option<Fingerprint> {
+    let mut scores: Vec<(Vendor, u8)> = Vec::new();
+    for rule in RULES.iter() {
+        let matched = match rule.signal {
+            Signal::Url(_) => page.chain.iter().any(|url| rule.regex.is_match(url)),
+            Signal::Body(_) => rule.regex.is_match(&page.body),
+            Signal::Header(name, _) => page
+                .headers
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .any(|value| rule.regex.is_match(value)),
+        };
+        if matched {
+            add(&mut scores, rule.vendor, rule.weight);
+        }
+    }
+    if best(&scores).is_none_or(|best| best.confidence < ACTIVE_CHECK_BELOW)
+        && let Ok(base) = Url::parse(&page.url)
+    {
+        let mut checks = JoinSet::new();
+        for (idx, (_, path, expected)) in WELL_KNOWN_PATHS.iter().enumerate() {
+            let client = client.clone();
+            let base = base.clone();
+            checks.spawn(async move { (idx, answers(&client, &base, path, expected).await) });
+        }
+        let mut answered = vec![false; WELL_KNOWN_PATHS.len()];
+        while let Some(joined) = checks.join_next().await {
+            if let Ok((idx, true)) = joined {
+                answered[idx] = true;
+            }
+        }
+        for ((vendor, path, _), answered) in WELL_KNOWN_PATHS.iter().zip(answered) {
+            if answered {
+                debug!("{} answered on {}", vendor, path);
+                add(&mut scores, *vendor, 50);
+            }
+        }
+    }
+    debug!("portal fingerprint scores: {:?}", scores);
+    best(&scores)
+}
+
+fn add(scores: &mut Vec<(Vendor, u8)>, vendor: Vendor, weight: u8) {
+    match scores.iter_mut().find(|(v, _)| *v == vendor) {
+        Some((_, score)) => *score = score.saturating_add(weight).min(100),
+        None => scores.push((vendor, weight.min(100))),
+    }
+}
+
+fn best(scores: &[(Vendor, u8)]) -> Option<Fingerprint> {
+    scores
+        .iter()
+        .max_by_key(|(_, score)| *score)
+        .map(|&(vendor, confidence)| Fingerprint { vendor, confidence })
+}
+
+async fn answers(client: &Client, base: &Url, path: &str, expected: &Regex) -> bool {
+    let Ok(url) = base.join(path) else {
+        return false;
+    };
+    let Ok(resp) = client.get(url).timeout(ACTIVE_CHECK_TIMEOUT).send().await else {
+        return false;
+    };
+    resp.status().is_success() && expected.is_match(&resp.text().await.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::captive::test_server::{self, not_found};
+    use tokio::net::TcpListener;
+
+    fn page_at(base: &str, body: &str) -> LoginPage {
+        page(&[&format!("{}/login", base)], None, body)
+    }
+    use reqwest::header::{HeaderMap, HeaderValue, SERVER};
+
+    fn page(chain: &[&str], server: Option<&'static str>, body: &str) -> LoginPage {
+        let mut headers = HeaderMap::new();
+        if let Some(server) = server {
+            headers.insert(SERVER, HeaderValue::from_static(server));
+        }
+        LoginPage {
+            url: chain.last().unwrap().to_string(),
+            headers,
+            body: body.to_string(),
+            chain: chain.iter().map(|url| url.to_string()).collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn identifies_vendors() {
+        let cases = [
+            (
+                page(
+                    &["http://10.0.0.1:1000/fgtauth?0123456789abcdef"],
+                    None,
+                    r#"<input type="hidden" name="magic" value="0123">"#,
+                ),
+                Some((Vendor::FortiGate, 100)),
+            ),
+            (
+                page(
+                    &["https://ise.example:8443/portal/PortalSetup.action?portal=1"],
+                    None,
+                    "",
+                ),
+                Some((Vendor::CiscoIse, 80)),
+            ),
+            (
+                page(
+                    &["https://1.1.1.1/login.html?switch_url=https://1.1.1.1/login.html"],
+                    None,
+                    r#"<input name="buttonClicked" value="0">"#,
+                ),
+                Some((Vendor::CiscoWlc, 90)),
+            ),
+            (
+                page(
+                    &["https://cp.example/guest/wifi.php?switchip=10.0.0.2"],
+                    None,
+                    "",
+                ),
+                Some((Vendor::ArubaClearPass, 70)),
+            ),
+            (
+                page(
+                    &["http://10.0.0.1:8002/index.php?zone=guest"],
+                    None,
+                    r#"<title>pfSense captive portal</title><input name="zone">"#,
+                ),
+                Some((Vendor::PfSense, 90)),
+            ),
+            (
+                page(
+                    &["http://10.0.0.1:8000/index.html"],
+                    None,
+                    r#"<script>$.post("/api/captiveportal/access/logon/0/")</script>"#,
+                ),
+                Some((Vendor::OpnSense, 70)),
+            ),
+            (
+                page(
+                    &["http://10.5.50.1/login?dst=http%3A%2F%2Fexample.com%2F"],
+                    Some("Mikrotik HttpProxy"),
+                    "",
+                ),
+                Some((Vendor::MikroTik, 60)),
+            ),
+            (
+                page(
+                    &[
+                        "http://neverssl.com/",
+                        "https://unifi.example:8843/guest/s/default/?id=aa:bb",
+                    ],
+                    None,
+                    "",
+                ),
+                Some((Vendor::UniFi, 80)),
+            ),
+            (
+                page(
+                    &["http://uam.example/?uamip=10.1.0.1&uamport=3990&challenge=00ff"],
+                    None,
+                    "",
+                ),
+                Some((Vendor::CoovaChilli, 80)),
+            ),
+            (
+                page(
+                    &["http://192.168.1.1:2050/opennds_preauth/?fas=abc"],
+                    Some("openNDS"),
+                    "",
+                ),
+                Some((Vendor::OpenNds, 100)),
+            ),
+        ];
+        let client = Client::new();
+        for (page, expected) in cases {
+            let found = identify(&client, &page)
+                .await
+                .map(|fingerprint| (fingerprint.vendor, fingerprint.confidence));
+            assert_eq!(found, expected, "{:?}", page.chain);
+        }
+    }
+
+    #[tokio::test]
+    async fn weak_signals_fall_back_to_well_known_paths() {
+        // nothing listens there, so no vendor path answers
+        let client = Client::new();
+        let plain = page(&["http://127.0.0.1:9/login"], None, "<p>Welcome</p>");
+        assert!(identify(&client, &plain).await.is_none());
+        let weak = page(
+            &["http://127.0.0.1:9/login"],
+            None,
+            "Powered by CoovaChilli",
+        );
+        let fingerprint = identify(&client, &weak).await.unwrap();
+        assert_eq!(fingerprint.vendor, Vendor::CoovaChilli);
+        assert_eq!(fingerprint.confidence, 30);
+    }
+
+    #[tokio::test]
+    async fn asks_the_well_known_paths() {
+        let base = test_server::serve(|path| match path {
+            "/json/status" => test_server::page(r#"{"clientState":0,"challenge":"00ff"}"#),
+            "/status" => {
+                test_server::page("<h1>Grand Hotel hotspot</h1><p>Welcome, MikroTik fans</p>")
+            }
+            _ => not_found(),
+        })
+        .await;
+        let weak = page_at(&base, "<p>Welcome</p>");
+        let fingerprint = identify(&test_server::client(), &weak).await.unwrap();
+        assert_eq!(fingerprint.vendor, Vendor::CoovaChilli);
+        assert_eq!(fingerprint.confidence, 50);
+
+        let base = test_server::serve(|path| match path {
+            "/status" => test_server::page(
+                r#"<form action="http://10.5.50.1/logout" name="logout" onSubmit="return openLogout()">"#,
+            ),
+            _ => not_found(),
+        })
+        .await;
+        let fingerprint = identify(&test_server::client(), &page_at(&base, ""))
+            .await
+            .unwrap();
+        assert_eq!(fingerprint.vendor, Vendor::MikroTik);
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_silent_portals_quickly() {
+        // accepts connections and never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let started = std::time::Instant::now();
+        assert!(
+            identify(&Client::new(), &page_at(&base, ""))
+                .await
+                .is_none()
+        );
+        assert!(started.elapsed() < ACTIVE_CHECK_TIMEOUT * 2);
+        drop(listener);
+    }
+}
@@ -0,0 +1,483 @@
+use regex::Regex;
+use reqwest::header::CONTENT_TYPE;
+use reqwest::multipart;
+use reqwest::{Client, Response, Url};
+use scraper::{ElementRef, Html, Selector};
+use std::sync::LazyLock;
+
+static TERMS_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)accept|agree|terms|\btos\b|\baup\b|conditions|policy").unwrap()
+});
+
+static PASSWORD_SEL: LazyLock<Selector> =
+    LazyLock::new(|| Selector::parse("input[type=password i]").unwrap());
+
+static USERNAME_RE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)user|login|e-?mail|uname|account|roll|^id$|^name$|^auth_?user$").unwrap()
+});
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FormMethod {
+    Get,
+    Post,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Enctype {
+    UrlEncoded,
+    Multipart,
+    TextPlain,
+}
+
+/// An HTML form as a browser would submit it.
+#[derive(Debug, Clone)]
+pub struct LoginForm {
+    pub action: Url,
+    pub method: FormMethod,
+    pub enctype: Enctype,
+    /// Every control the browser would send, in document order: hidden and
+    /// pre-filled inputs, checked boxes, selects and the submit button.
+    pub fields: Vec<(String, String)>,
+    pub username_field: Option<String>,
+    pub password_field: Option<String>,
+    /// Checkboxes left unticked by the page, which the browser wouldn't send.
+    pub unchecked: Vec<Checkbox>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Checkbox {
+    pub name: String,
+    pub value: String,
+    pub required: bool,
+}
+
+impl LoginForm {
+    /// Picks the form on the page that looks most like a login form. Only
+    /// forms with a password input are considered.
+    pub fn find(page_url: &str, html: &str) -> Option<Self> {
+        let doc = Html::parse_document(html);
+        let base = base_url(&doc, page_url)?;
+        let form_sel = Selector::parse("form").unwrap();
+        let mut best: Option<(u32, ElementRef)> = None;
+        for form in doc.select(&form_sel) {
+            let score = login_score(form);
+            if score > 0 && best.is_none_or(|(best_score, _)| score > best_score) {
+                best = Some((score, form));
+            }
+        }
+        best.map(|(_, form)| Self::from_element(&base, form))
+    }
+
+    /// Picks the form that asks to accept terms of service: no password input,
+    /// and a checkbox or wording about accepting or agreeing.
+    pub fn find_terms(page_url: &str, html: &str) -> Option<Self> {
+        let doc = Html::parse_document(html);
+        let base = base_url(&doc, page_url)?;
+        let form_sel = Selector::parse("form").unwrap();
+        let checkbox_sel = Selector::parse("input[type=checkbox i]").unwrap();
+        doc.select(&form_sel)
+            .filter(|form| form.select(&PASSWORD_SEL).next().is_none())
+            .find(|form| {
+                form.select(&checkbox_sel).next().is_some() || TERMS_RE.is_match(&form.html())
+            })
+            .map(|form| Self::from_element(&base, form))
+    }
+
+    /// The first form on the page matching `selector`.
+    pub fn select(page_url: &str, html: &str, selector: &Selector) -> Option<Self> {
+        let doc = Html::parse_document(html);
+        let base = base_url(&doc, page_url)?;
+        let form = doc.select(selector).next()?;
+        Some(Self::from_element(&base, form))
+    }
+
+    pub fn from_element(base: &Url, form: ElementRef) -> Self {
+        let attr = |name: &str| form.value().attr(name).map(str::trim);
+        let action = match attr("action") {
+            Some(action) if !action.is_empty() => {
+                base.join(action).unwrap_or_else(|_| base.clone())
+            }
+            _ => base.clone(),
+        };
+        let method = match attr("method") {
+            Some(method) if method.eq_ignore_ascii_case("get") => FormMethod::Get,
+            _ => FormMethod::Post,
+        };
+        let enctype = match attr("enctype") {
+            Some(enctype) if enctype.eq_ignore_ascii_case("multipart/form-data") => {
+                Enctype::Multipart
+            }
+            Some(enctype) if enctype.eq_ignore_ascii_case("text/plain") => Enctype::TextPlain,
+            _ => Enctype::UrlEncoded,
+        };
+
+        let mut fields = Vec::new();
+        let mut unchecked = Vec::new();
+        let mut password_field = None;
+        let mut text_fields: Vec<String> = Vec::new();
+        let mut text_before_password: Option<String> = None;
+        let control_sel = Selector::parse("input, select, textarea").unwrap();
+        for control in form.select(&control_sel) {
+            let el = control.value();
+            let Some(name) = el.attr("name").filter(|name| !name.is_empty()) else {
+                continue;
+            };
+            if el.attr("disabled").is_some() {
+                continue;
+            }
+            match el.name() {
+                "select" => {
+                    let option_sel = Selector::parse("option").unwrap();
+                    let selected = control
+                        .select(&option_sel)
+                        .find(|option| option.value().attr("selected").is_some())
+                        .or_else(|| control.select(&option_sel).next());
+                    if let Some(option) = selected {
+                        let value = option
+                            .value()
+                            .attr("value")
+                            .map(str::to_string)
+                            .unwrap_or_else(|| {
+                                option.text().collect::<String>().trim().to_string()
+                            });
+                        fields.push((name.to_string(), value));
+                    }
+                }
+                "textarea" => fields.push((name.to_string(), control.text().collect())),
+                _ => {
+                    let kind = el.attr("type").unwrap_or("text").to_ascii_lowercase();
+                    let value = el.attr("value").unwrap_or("").to_string();
+                    match kind.as_str() {
+                        "submit" | "image" | "button" | "reset" | "file" => continue,
+                        "checkbox" | "radio" => {
+                            let value = el.attr("value").unwrap_or("on").to_string();
+                            if el.attr("checked").is_some() {
+                                fields.push((name.to_string(), value));
+                            } else if kind == "checkbox" {
+                                unchecked.push(Checkbox {
+                                    name: name.to_string(),
+                                    value,
+                                    required: el.attr("required").is_some(),
+                                });
+                            }
+                            continue;
+                        }
+                        "password" => {
+                            if password_field.is_none() {
+                                password_field = Some(name.to_string());
+                                text_before_password = text_fields.last().cloned();
+                            }
+                        }
+                        "hidden" => {}
+                        _ => text_fields.push(name.to_string()),
+                    }
+                    fields.push((name.to_string(), value));
+                }
+            }
+        }
+
+        // the first submit button is the one pressing enter would use
+        let button_sel = Selector::parse("input, button").unwrap();
+        let submit = form.select(&button_sel).find(|el| {
+            let kind = el.value().attr("type").map(str::to_ascii_lowercase);
+            match el.value().name() {
+                "button" => kind.as_deref().is_none_or(|kind| kind == "submit"),
+                _ => kind.as_deref() == Some("submit"),
+            }
+        });
+        if let Some(button) = submit
+            && let Some(name) = button.value().attr("name").filter(|name| !name.is_empty())
+        {
+            let value = button.value().attr("value").unwrap_or("");
+            fields.push((name.to_string(), value.to_string()));
+        }
+
+        let username_field = text_fields
+            .iter()
+            .find(|name| USERNAME_RE.is_match(name))
+            .cloned()
+            .or(text_before_password)
+            .or_else(|| text_fields.first().cloned());
+
+        Self {
+            action,
+            method,
+            enctype,
+            fields,
+            username_field,
+            password_field,
+            unchecked,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Sets `name` to `value`, adding the field if the form doesn't have it.
+    pub fn set(&mut self, name: &str, value: &str) {
+        match self.fields.iter_mut().find(|(field, _)| field == name) {
+            Some((_, existing)) => *existing = value.to_string(),
+            None => self.fields.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Ticks the unchecked boxes the portal wants ticked before it lets us on:
+    /// required ones, ones about the terms, or the only one there is. Returns
+    /// how many were ticked.
+    pub fn accept_terms(&mut self) -> usize {
+        let only_one = self.unchecked.len() == 1;
+        let (tick, keep): (Vec<_>, Vec<_>) = self.unchecked.drain(..).partition(|checkbox| {
+            only_one || checkbox.required || TERMS_RE.is_match(&checkbox.name)
+        });
+        self.unchecked = keep;
+        for checkbox in &tick {
+            self.fields
+                .push((checkbox.name.clone(), checkbox.value.clone()));
+        }
+        tick.len()
+    }
+
+    /// Puts the credentials into whatever the form calls its username and
+    /// password fields. Returns false when the form has no password field.
+    pub fn fill_credentials(&mut self, username: &str, password: &str) -> bool {
+        let Some(password_field) = self.password_field.clone() else {
+            return false;
+        };
+        if let Some(username_field) = self.username_field.clone() {
+            self.set(&username_field, username);
+        }
+        self.set(&password_field, password);
+        true
+    }
+
+    pub async fn submit(&self, client: &Client) -> reqwest::Result<Response> {
+        let request = match (self.method, self.enctype) {
+            (FormMethod::Get, _) => {
+                let mut url = self.action.clone();
+                url.set_query(None);
+                url.query_pairs_mut().extend_pairs(&self.fields);
+                client.get(url)
+            }
+            (FormMethod::Post, Enctype::UrlEncoded) => {
+                client.post(self.action.clone()).form(&self.fields)
+            }
+            (FormMethod::Post, Enctype::Multipart) => {
+                let form = self
+                    .fields
+                    .iter()
+                    .fold(multipart::Form::new(), |form, (name, value)| {
+                        form.text(name.clone(), value.clone())
+                    });
+                client.post(self.action.clone()).multipart(form)
+            }
+            (FormMethod::Post, Enctype::TextPlain) => {
+                let body: String = self
+                    .fields
+                    .iter()
+                    .map(|(name, value)| format!("{}={}\r\n", name, value))
+                    .collect();
+                client
+                    .post(self.action.clone())
+                    .header(CONTENT_TYPE, "text/plain")
+                    .body(body)
+            }
+        };
+        request.send().await
+    }
+}
+
+/// Whether the page has a password input anywhere, as a portal that turned
+/// the credentials down and shows the login form again does.
+pub fn asks_for_password(html: &str) -> bool {
+    Html::parse_document(html)
+        .select(&PASSWORD_SEL)
+        .next()
+        .is_some()
+}
+
+/// The text of the page as a browser shows it, leaving out scripts and
+/// styles, whose words say nothing about the outcome.
+pub fn visible_text(html: &str) -> String {
+    Html::parse_document(html)
+        .root_element()
+        .descendants()
+        .filter(|node| {
+            node.parent()
+                .and_then(|parent| parent.value().as_element())
+                .is_none_or(|element| !matches!(element.name(), "script" | "style"))
+        })
+        .filter_map(|node| node.value().as_text())
+        .map(|text| text.trim())
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The URL relative actions resolve against, honouring `<base href>`.
+fn base_url(doc: &Html, page_url: &str) -> Option<Url> {
+    let page = Url::parse(page_url).ok()?;
+    let base_sel = Selector::parse("base[href]").unwrap();
+    Some(
+        doc.select(&base_sel)
+            .next()
+            .and_then(|base| page.join(base.value().attr("href")?).ok())
+            .unwrap_or(page),
+    )
+}
+
+fn login_score(form: ElementRef) -> u32 {
+    let input_sel = Selector::parse("input").unwrap();
+    let mut has_password = false;
+    let mut has_username = false;
+    for input in form.select(&input_sel) {
+        let kind = input.value().attr("type").unwrap_or("text");
+        let name = input.value().attr("name").unwrap_or("");
+        if kind.eq_ignore_ascii_case("password") {
+            has_password = true;
+        } else if matches!(kind.to_ascii_lowercase().as_str(), "text" | "email" | "tel")
+            && USERNAME_RE.is_match(name)
+        {
+            has_username = true;
+        }
+    }
+    match (has_password, has_username) {
+        (false, _) => 0,
+        (true, false) => 10,
+        (true, true) => 13,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = "http://10.0.0.1/portal/login.html";
+
+    #[test]
+    fn picks_the_login_form() {
+        let html = r#"
+            <form action="/search"><input name="q"></form>
+            <form action="/signup"><input name="nick"><input type="password" name="new"></form>
+            <form action="login.cgi" method="GET">
+                <input name="email"><input type="PASSWORD" name="secret">
+            </form>"#;
+        let form = LoginForm::find(PAGE, html).unwrap();
+        assert_eq!(form.action.as_str(), "http://10.0.0.1/portal/login.cgi");
+        assert_eq!(form.method, FormMethod::Get);
+        assert_eq!(form.username_field.as_deref(), Some("email"));
+        assert_eq!(form.password_field.as_deref(), Some("secret"));
+
+        assert!(LoginForm::find(PAGE, r#"<form><input name="q"></form>"#).is_none());
+    }
+
+    #[test]
+    fn guesses_the_username_field() {
+        let cases = [
+            // a name that says so wins
+            (
+                r#"<input name="room"><input name="roll_no"><input type="password" name="pw">"#,
+                "roll_no",
+            ),
+            // else the text input right before the password
+            (
+                r#"<input name="room"><input name="code"><input type="password" name="pw">"#,
+                "code",
+            ),
+            // else the first text input
+            (
+                r#"<input type="password" name="pw"><input name="room">"#,
+                "room",
+            ),
+        ];
+        for (inputs, expected) in cases {
+            let form = LoginForm::find(PAGE, &format!("<form>{}</form>", inputs)).unwrap();
+            assert_eq!(form.username_field.as_deref(), Some(expected), "{}", inputs);
+        }
+    }
+
+    #[test]
+    fn carries_fields_like_a_browser() {
+        let html = r#"
+            <base href="http://portal.example/auth/">
+            <form action="submit" enctype="Multipart/Form-Data">
+                <input type="hidden" name="magic" value="42">
+                <input name="user" value="prefilled">
+                <input type="password" name="pass">
+                <input name="off" value="x" disabled>
+                <input type="checkbox" name="remember" value="yes" checked>
+                <input type="checkbox" name="accept_terms" required>
+                <input type="radio" name="plan" value="free">
+                <select name="lang"><option value="en">English</option><option selected>Deutsch</option></select>
+                <textarea name="note">hi</textarea>
+                <input type="submit" name="login" value="Sign in">
+                <button name="cancel" value="1">Cancel</button>
+            </form>"#;
+        let mut form = LoginForm::find(PAGE, html).unwrap();
+        assert_eq!(form.action.as_str(), "http://portal.example/auth/submit");
+        assert_eq!(form.method, FormMethod::Post);
+        assert_eq!(form.enctype, Enctype::Multipart);
+        let fields: Vec<(&str, &str)> = form
+            .fields
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("magic", "42"),
+                ("user", "prefilled"),
+                ("pass", ""),
+                ("remember", "yes"),
+                ("lang", "Deutsch"),
+                ("note", "hi"),
+                ("login", "Sign in"),
+            ]
+        );
+        assert_eq!(form.unchecked.len(), 1);
+        assert!(form.unchecked[0].required);
+
+        assert!(form.fill_credentials("jdoe", "hunter2"));
+        assert_eq!(form.get("user"), Some("jdoe"));
+        assert_eq!(form.get("pass"), Some("hunter2"));
+        assert_eq!(form.accept_terms(), 1);
+        assert_eq!(form.get("accept_terms"), Some("on"));
+    }
+
+    #[test]
+    fn finds_terms_forms() {
+        let html = r#"
+            <form action="/login"><input name="user"><input type="Password" name="pw"></form>
+            <form action="/accept"><input type="CHECKBOX" name="tos"><input type="checkbox" name="news"></form>"#;
+        let mut form = LoginForm::find_terms(PAGE, html).unwrap();
+        assert_eq!(form.action.as_str(), "http://10.0.0.1/accept");
+        assert_eq!(form.accept_terms(), 1);
+        assert_eq!(form.get("tos"), Some("on"));
+        assert_eq!(form.get("news"), None);
+
+        let wording =
+            r#"<form action="/go"><p>I agree to the terms</p><button>Continue</button></form>"#;
+        assert!(LoginForm::find_terms(PAGE, wording).is_some());
+        assert!(
+            LoginForm::find_terms(PAGE, r#"<form action="/search"><input name="q"></form>"#)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn asks_for_password_in_any_case() {
+        assert!(asks_for_password(r#"<input type="password" name="pw">"#));
+        assert!(asks_for_password(r#"<INPUT TYPE="PASSWORD" NAME="pw">"#));
+        assert!(!asks_for_password(r#"<input type="text" name="password">"#));
+    }
+
+    #[test]
+    fn leaves_scripts_and_styles_out_of_the_text() {
+        let html = r#"<html><head><title>Portal</title><style>.failed { color: red }</style></head>
+            <body><h1>Welcome</h1><script>if (failed) { alert("denied"); }</script><p>Enjoy <b>the</b> wifi</p></body></html>"#;
+        assert_eq!(visible_text(html), "Portal Welcome Enjoy the wifi");
+    }
+}
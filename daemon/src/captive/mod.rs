@@ -0,0 +1,342 @@
+pub mod capport;
+pub mod detect;
+pub mod discovery;
+pub mod drivers;
+pub mod error;
+pub mod fingerprint;
+pub mod form;
+pub mod session;
+#[cfg(test)]
+mod test_server;
+
+use log::{debug, info, warn};
+use reqwest::Url;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::captive::capport::CapportState;
+use crate::configs::{Config, ProbeEndpoint, Profile, VotePolicy};
+use crate::event::Event;
+use detect::Verdict;
+use drivers::{PortalDriver, PortalSession};
+use error::CaptiveError;
+use fingerprint::Fingerprint;
+use session::HttpSession;
+
+/// How long before the Captive Portal API says the session ends we log in again.
+const RELOGIN_MARGIN: Duration = Duration::from_secs(60);
+
+pub struct Captive {
+    /// The Captive Portal API from the config, or else as the network
+    /// advertised it.
+    capport_url: Option<String>,
+    capport_configured: bool,
+    /// What the Captive Portal API said last time we asked.
+    capport: Option<CapportState>,
+    probes: Vec<ProbeEndpoint>,
+    probe_policy: VotePolicy,
+    portal_url: String,
+    timeout: Duration,
+    /// Clients of the current login attempt, replaced on every probe.
+    http: HttpSession,
+    keep_cookies: bool,
+    /// Cookies of earlier attempts, by portal host.
+    saved_cookies: HashMap<String, cookie_store::CookieStore>,
+    drivers: Vec<Box<dyn PortalDriver>>,
+    /// The vendor the last login page looked like.
+    fingerprint: Option<Fingerprint>,
+    session: Option<ActiveSession>,
+}
+
+/// What we know about the portal we are behind, for the control API.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PortalStatus {
+    pub fingerprint: Option<Fingerprint>,
+    /// The last Captive Portal API answer, counted down to now.
+    pub api: Option<CapportState>,
+}
+
+/// The session opened by the last successful login.
+struct ActiveSession {
+    driver: usize,
+    info: PortalSession,
+    http: HttpSession,
+    refreshed: Instant,
+}
+
+impl Captive {
+    pub fn new(config: &Config) -> Self {
+        let timeout = Duration::from_secs(config.timeouts);
+        Self {
+            capport_url: config.capport_url.clone(),
+            capport_configured: config.capport_url.is_some(),
+            capport: None,
+            probes: config.probes.clone(),
+            probe_policy: config.probe_policy,
+            portal_url: String::from(""),
+            timeout,
+            http: HttpSession::new(timeout),
+            keep_cookies: config.keep_cookies,
+            saved_cookies: HashMap::new(),
+            drivers: drivers::load(&config.portals_dir()),
+            fingerprint: None,
+            session: None,
+        }
+    }
+    /// Takes the settings of a new config, keeping the portal session and
+    /// what we know about the network.
+    pub fn reconfigure(&mut self, config: &Config) {
+        if config.capport_url.is_some() || self.capport_configured {
+            self.capport_url = config.capport_url.clone();
+            self.capport = None;
+        }
+        self.capport_configured = config.capport_url.is_some();
+        self.probes = config.probes.clone();
+        self.probe_policy = config.probe_policy;
+        self.timeout = Duration::from_secs(config.timeouts);
+        self.keep_cookies = config.keep_cookies;
+        if !self.keep_cookies {
+            self.saved_cookies.clear();
+        }
+        // the session keeps pointing at its driver by index
+        if self.session.is_none() {
+            self.drivers = drivers::load(&config.portals_dir());
+        }
+    }
+
+    /// Starts a new login attempt by probing for a portal. The Captive Portal
+    /// API is asked first when we know it, the probe endpoints otherwise.
+    pub async fn probe(&mut self) -> Verdict {
+        self.http = HttpSession::new(self.timeout);
+        if let Some(verdict) = self.query_capport().await {
+            info!("captive portal API verdict: {:?}", verdict);
+            if let Verdict::Captive(url) = &verdict {
+                self.portal_url = url.clone();
+            }
+            return verdict;
+        }
+        let verdict =
+            detect::probe_all(&self.http.no_redirect, &self.probes, self.probe_policy).await;
+        if let Verdict::Captive(url) = &verdict {
+            self.portal_url = url.clone();
+        }
+        verdict
+    }
+
+    async fn query_capport(&mut self) -> Option<Verdict> {
+        let url = self.capport_url.as_ref()?;
+        let state = match capport::fetch(&self.http.client, url).await {
+            Ok(state) => state,
+            Err(e) => {
+                warn!("{}, probing instead", e);
+                self.capport = None;
+                return None;
+            }
+        };
+        debug!("captive portal API status: {:?}", state.status());
+        let status = state.status();
+        let verdict = if status.captive || state.expiring(RELOGIN_MARGIN) {
+            // without a portal URL only the probes can tell us where to log in
+            status.user_portal_url.clone().map(Verdict::Captive)
+        } else {
+            Some(Verdict::Open)
+        };
+        self.capport = Some(state);
+        verdict
+    }
+
+    /// Takes the Captive Portal API URI the network advertised, unless the
+    /// config names one.
+    pub fn set_advertised_capport(&mut self, uri: Option<String>) {
+        if self.capport_configured || uri == self.capport_url {
+            return;
+        }
+        info!("captive portal API is now {:?}", uri);
+        self.capport_url = uri;
+        self.capport = None;
+    }
+
+    /// Whether a Captive Portal API is known, making its answer
+    /// authoritative over other connectivity checks.
+    pub fn has_capport(&self) -> bool {
+        self.capport_url.is_some()
+    }
+
+    pub fn status(&self) -> PortalStatus {
+        PortalStatus {
+            fingerprint: self.fingerprint,
+            api: self.capport.clone(),
+        }
+    }
+
+    /// How long until the session needs logging into again, if the Captive
+    /// Portal API or the driver said when it ends. A data allowance has the
+    /// API asked again every so often.
+    pub fn relogin_in(&self) -> Option<Duration> {
+        let capport = self
+            .capport
+            .as_ref()
+            .and_then(CapportState::remaining)
+            .map(|left| left.saturating_sub(RELOGIN_MARGIN));
+        let bytes = self.capport.as_ref().and_then(CapportState::recheck_in);
+        let session = self
+            .session
+            .as_ref()
+            .and_then(|session| session.info.expires)
+            .map(|expires| expires.saturating_duration_since(Instant::now()));
+        capport.into_iter().chain(bytes).chain(session).min()
+    }
+
+    pub async fn login(&mut self, profile: &Profile) -> Result<Event, CaptiveError> {
+        let network = Url::parse(&self.portal_url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string));
+        if self.keep_cookies
+            && let Some(saved) = network
+                .as_ref()
+                .and_then(|host| self.saved_cookies.get(host))
+        {
+            debug!("restoring saved cookies for {:?}", network);
+            self.http.cookies.restore(saved);
+        }
+        let event = self.attempt_login(profile).await;
+        if self.keep_cookies
+            && let Some(host) = network
+        {
+            self.saved_cookies
+                .insert(host, self.http.cookies.snapshot());
+        }
+        event
+    }
+
+    async fn attempt_login(&mut self, profile: &Profile) -> Result<Event, CaptiveError> {
+        let page = discovery::discover(&self.http.no_redirect, &self.portal_url).await?;
+        self.fingerprint = fingerprint::identify(&self.http.no_redirect, &page).await;
+        match &self.fingerprint {
+            Some(fingerprint) => info!("portal at {} looks like {}", page.url, fingerprint),
+            None => info!("portal at {} matches no known vendor", page.url),
+        }
+        let Some(idx) = self
+            .drivers
+            .iter()
+            .position(|driver| driver.supports(profile) && driver.detect(&page))
+        else {
+            match &self.fingerprint {
+                Some(fingerprint) => warn!(
+                    "detected {} at {}, no driver configured for it",
+                    fingerprint, page.url
+                ),
+                None => warn!("no portal driver recognised {}", page.url),
+            }
+            return Ok(Event::NoDriver);
+        };
+        let driver = &self.drivers[idx];
+        info!("using the {} driver for {}", driver.name(), page.url);
+        let mut outcome = driver.login(&self.http, &page, profile).await?;
+        // nothing in the answer says whether the terms were accepted, or the
+        // driver didn't recognise the answer
+        if matches!(outcome.event, Event::Unknown)
+            || matches!(outcome.event, Event::Success)
+                && matches!(profile, Profile::ClickThrough { .. })
+        {
+            let verdict = self.confirm().await;
+            outcome.event = if verdict == Verdict::Open {
+                Event::Success
+            } else {
+                warn!("still no connectivity after the login: {:?}", verdict);
+                Event::Unknown
+            };
+        }
+        if matches!(outcome.event, Event::Success) {
+            info!(
+                "portal session opened, keepalive: {:?}, logout: {:?}",
+                outcome.session.keepalive_url, outcome.session.logout_url
+            );
+            self.session = Some(ActiveSession {
+                driver: idx,
+                info: outcome.session,
+                http: self.http.clone(),
+                refreshed: Instant::now(),
+            });
+        }
+        Ok(outcome.event)
+    }
+
+    /// Probes like `probe` does, but in the session the login happened in.
+    async fn confirm(&mut self) -> Verdict {
+        if let Some(verdict) = self.query_capport().await {
+            return verdict;
+        }
+        detect::probe_all(&self.http.no_redirect, &self.probes, self.probe_policy).await
+    }
+
+    /// The portal the last probe found.
+    pub fn portal_url(&self) -> Option<String> {
+        Some(self.portal_url.clone()).filter(|url| !url.is_empty())
+    }
+
+    /// The host of the portal the last probe found.
+    pub fn portal_host(&self) -> Option<String> {
+        Url::parse(&self.portal_url)
+            .ok()?
+            .host_str()
+            .map(str::to_string)
+    }
+
+    /// Whether the active session has a keepalive that is older than `interval`.
+    pub fn keepalive_due(&self, interval: Duration) -> bool {
+        self.keepalive_in(interval)
+            .is_some_and(|left| left.is_zero())
+    }
+
+    /// How long until the active session's keepalive is `interval` old, if it
+    /// has one.
+    pub fn keepalive_in(&self, interval: Duration) -> Option<Duration> {
+        if interval.is_zero() {
+            return None;
+        }
+        let session = self.session.as_ref()?;
+        session.info.keepalive_url.as_ref()?;
+        Some(interval.saturating_sub(session.refreshed.elapsed()))
+    }
+
+    /// A failed keepalive request leaves the session as it is, the portal may
+    /// only have been unreachable for a moment.
+    pub async fn keepalive(&mut self) -> Result<Event, CaptiveError> {
+        let Some(session) = &mut self.session else {
+            return Ok(Event::SessionExpired);
+        };
+        let event = self.drivers[session.driver]
+            .keepalive(&session.http.client, &session.info)
+            .await?;
+        match event {
+            Event::KeepAlive => session.refreshed = Instant::now(),
+            _ => {
+                warn!("portal session expired, logging in again");
+                self.session = None;
+            }
+        }
+        Ok(event)
+    }
+
+    pub fn has_session(&self) -> bool {
+        self.session.is_some()
+    }
+
+    /// Ends the active portal session, if there is one.
+    pub async fn logout(&mut self) -> Result<Event, CaptiveError> {
+        let Some(session) = &self.session else {
+            warn!("logout requested without an active portal session");
+            return Ok(Event::LogoutFailed);
+        };
+        let event = self.drivers[session.driver]
+            .logout(&session.http.client, &session.info)
+            .await?;
+        if matches!(event, Event::LoggedOut) {
+            info!("logged out of the portal");
+            self.session = None;
+        }
+        Ok(event)
+    }
+}
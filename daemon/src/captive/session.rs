@@ -0,0 +1,85 @@
+use cookie_store::RawCookie;
+use reqwest::Client;
+use reqwest::Url;
+use reqwest::header::HeaderValue;
+use reqwest::redirect;
+use std::convert::Infallible;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// The HTTP clients of one login attempt. Both share a cookie jar so a cookie
+/// set by the splash page comes back on the form submission.
+#[derive(Clone)]
+pub struct HttpSession {
+    /// Follows redirects, for talking to the portal once we know where it is.
+    pub client: Client,
+    /// Doesn't follow redirects, for probing and discovery where the
+    /// redirect itself is what we're after.
+    pub no_redirect: Client,
+    pub cookies: Arc<CookieJar>,
+}
+
+impl HttpSession {
+    pub fn new(timeout: Duration) -> Self {
+        let cookies = Arc::new(CookieJar::default());
+        let builder = || {
+            Client::builder()
+                .pool_max_idle_per_host(0)
+                .timeout(timeout)
+                .cookie_provider(cookies.clone())
+        };
+        Self {
+            client: builder().build().expect("failed to build Client"),
+            no_redirect: builder()
+                .redirect(redirect::Policy::none())
+                .build()
+                .expect("failed to build Client"),
+            cookies: cookies.clone(),
+        }
+    }
+}
+
+/// A cookie jar that, unlike `reqwest::cookie::Jar`, can be copied out and
+/// restored later to keep a network's cookies between login attempts.
+#[derive(Default)]
+pub struct CookieJar(RwLock<cookie_store::CookieStore>);
+
+impl CookieJar {
+    pub fn snapshot(&self) -> cookie_store::CookieStore {
+        self.0.read().unwrap().clone()
+    }
+
+    /// Adds `saved` to the jar. Cookies already in the jar win, they are newer.
+    pub fn restore(&self, saved: &cookie_store::CookieStore) {
+        let mut store = self.0.write().unwrap();
+        let merged = saved
+            .iter_unexpired()
+            .chain(store.iter_unexpired())
+            .cloned()
+            .map(Ok::<_, Infallible>);
+        *store = cookie_store::CookieStore::from_cookies(merged, false).unwrap();
+    }
+}
+
+impl reqwest::cookie::CookieStore for CookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let cookies = cookie_headers
+            .filter_map(|header| header.to_str().ok())
+            .filter_map(|header| RawCookie::parse(header.to_string()).ok());
+        self.0.write().unwrap().store_response_cookies(cookies, url);
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let store = self.0.read().unwrap();
+        let header = store
+            .get_request_values(url)
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        if header.is_empty() {
+            None
+        } else {
+            HeaderValue::from_str(&header).ok()
+        }
+    }
+}
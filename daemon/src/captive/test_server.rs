@@ -0,0 +1,55 @@
+//! A throwaway HTTP server for tests that need a portal to talk to.
+
+use reqwest::Client;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// The status line and extra headers of a response, and its body.
+pub type Reply = (String, String);
+
+/// Serves on a free local port until the test ends, answering every request
+/// with what `route` gives for its path and query. Returns the base URL.
+pub async fn serve(route: fn(&str) -> Reply) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut buf = [0; 1024];
+            let n = stream.read(&mut buf).await.unwrap_or(0);
+            let request = String::from_utf8_lossy(&buf[..n]);
+            let (head, body) = route(request.split(' ').nth(1).unwrap_or("/"));
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n{}",
+                head,
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    });
+    format!("http://{}", addr)
+}
+
+pub fn page(body: &str) -> Reply {
+    status("200 OK", body)
+}
+
+pub fn redirect(to: &str) -> Reply {
+    status(&format!("302 Found\r\nLocation: {}", to), "")
+}
+
+pub fn status(head: &str, body: &str) -> Reply {
+    (head.to_string(), body.to_string())
+}
+
+pub fn not_found() -> Reply {
+    status("404 Not Found", "")
+}
+
+/// A client that leaves redirects to the caller, as the probes use.
+pub fn client() -> Client {
+    Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
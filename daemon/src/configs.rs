@@ -1,16 +1,563 @@
+use rand::Rng;
 use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::event::Event;
+use crate::platform::LinkInfo;
 
 #[derive(Deserialize, Debug)]
-pub struct Profile {
-    pub rollno: String,
-    pub password: String,
+#[serde(untagged)]
+pub enum Profile {
+    Credentials {
+        rollno: String,
+        password: String,
+    },
+    /// A voucher code for portals that hand out vouchers instead of accounts.
+    Voucher {
+        voucher: String,
+    },
+    /// For portals that only ask to accept their terms of service.
+    ClickThrough {
+        accept_terms: bool,
+    },
+}
+
+impl Profile {
+    pub fn credentials(&self) -> Option<(&str, &str)> {
+        match self {
+            Profile::Credentials { rollno, password } => Some((rollno, password)),
+            Profile::Voucher { .. } | Profile::ClickThrough { .. } => None,
+        }
+    }
+}
+
+/// A profile for the networks its rules match.
+#[derive(Deserialize, Debug)]
+pub struct NetworkProfile {
+    pub name: String,
+    #[serde(default, rename = "match")]
+    pub rules: MatchRules,
+    #[serde(flatten)]
+    pub profile: Profile,
+}
+
+/// Every rule that is set has to match, by any of its values. A profile
+/// without rules matches every network.
+#[derive(Deserialize, Debug, Default)]
+pub struct MatchRules {
+    #[serde(default)]
+    pub ssid: Vec<String>,
+    #[serde(default)]
+    pub bssid: Vec<String>,
+    #[serde(default)]
+    pub gateway_mac: Vec<String>,
+    #[serde(default)]
+    pub gateway_ip: Vec<String>,
+    /// The portal's host, or a domain it is under.
+    #[serde(default)]
+    pub portal_host: Vec<String>,
+}
+
+impl MatchRules {
+    pub fn matches(&self, link: &LinkInfo, portal_host: Option<&str>) -> bool {
+        let rule = |values: &[String], actual: Option<&str>, same: fn(&str, &str) -> bool| {
+            values.is_empty() || actual.is_some_and(|actual| values.iter().any(|v| same(v, actual)))
+        };
+        rule(&self.ssid, link.ssid.as_deref(), |a, b| a == b)
+            && rule(&self.bssid, link.bssid.as_deref(), same_mac)
+            && rule(&self.gateway_mac, link.gateway_mac.as_deref(), same_mac)
+            && rule(
+                &self.gateway_ip,
+                link.gateway.map(|ip| ip.to_string()).as_deref(),
+                |a, b| a == b,
+            )
+            && rule(&self.portal_host, portal_host, |domain, host| {
+                host.eq_ignore_ascii_case(domain)
+                    || host
+                        .to_ascii_lowercase()
+                        .ends_with(&format!(".{}", domain.to_ascii_lowercase()))
+            })
+    }
+}
+
+/// MAC addresses compared the way people write them: any case, `:` or `-`.
+fn same_mac(a: &str, b: &str) -> bool {
+    let normalize = |mac: &str| mac.to_ascii_lowercase().replace('-', ":");
+    normalize(a) == normalize(b)
 }
 
 #[derive(Deserialize, Debug)]
 pub struct Config {
     pub refresh: u64,
-    pub profile: Profile,
+    /// Seconds between checks instead of `refresh` while the platform tells
+    /// us about network changes.
+    #[serde(default = "default_watched_refresh")]
+    pub watched_refresh: u64,
+    /// Used on every network no entry in `profiles` matches.
+    pub profile: Option<Profile>,
+    #[serde(default)]
+    pub profiles: Vec<NetworkProfile>,
     pub timeouts: u64,
+    pub portals_dir: Option<PathBuf>,
+    /// RFC 8908 Captive Portal API of the network, if it has one.
+    pub capport_url: Option<String>,
+    /// Seconds between keepalives of an open portal session, 0 disables them.
+    #[serde(default = "default_keepalive")]
+    pub keepalive: u64,
+    #[serde(default = "default_probes")]
+    pub probes: Vec<ProbeEndpoint>,
+    #[serde(default)]
+    pub probe_policy: VotePolicy,
+    /// Keep portal cookies between login attempts on the same network.
+    #[serde(default)]
+    pub keep_cookies: bool,
+    #[serde(default)]
+    pub retry: RetryPolicies,
+}
+
+/// How failed logins are retried, by what they failed with.
+#[derive(Deserialize, Debug, Default)]
+pub struct RetryPolicies {
+    /// For every outcome without a policy of its own.
+    #[serde(default)]
+    pub default: RetryPolicy,
+    #[serde(flatten)]
+    pub events: HashMap<Event, RetryOverrides>,
+}
+
+impl RetryPolicies {
+    /// The policy of `event`: its own fields over the built-in policy for the
+    /// event, which builds on `default`.
+    pub fn for_event(&self, event: Event) -> RetryPolicy {
+        let builtin = match event {
+            // portals lock accounts out after a few wrong passwords
+            Event::WrongCreds => RetryPolicy {
+                max_attempts: 1,
+                ..self.default
+            },
+            // the other devices' sessions won't end any time soon
+            Event::MaxConcurrent => RetryPolicy {
+                initial: 300,
+                max: 3600,
+                ..self.default
+            },
+            _ => self.default,
+        };
+        self.events
+            .get(&event)
+            .map_or(builtin, |overrides| overrides.over(builtin))
+    }
+}
+
+/// The fields a `[retry.<Event>]` table sets, the rest come from the policy
+/// it overrides.
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+pub struct RetryOverrides {
+    pub initial: Option<u64>,
+    pub multiplier: Option<f64>,
+    pub max: Option<u64>,
+    pub jitter: Option<f64>,
+    pub max_attempts: Option<u32>,
+}
+
+impl RetryOverrides {
+    fn over(&self, policy: RetryPolicy) -> RetryPolicy {
+        RetryPolicy {
+            initial: self.initial.unwrap_or(policy.initial),
+            multiplier: self.multiplier.unwrap_or(policy.multiplier),
+            max: self.max.unwrap_or(policy.max),
+            jitter: self.jitter.unwrap_or(policy.jitter),
+            max_attempts: self.max_attempts.unwrap_or(policy.max_attempts),
+        }
+    }
+}
+
+/// Waits between attempts growing from `initial` by `multiplier` up to `max`
+/// seconds, with up to `jitter` of each wait added or taken off at random.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+pub struct RetryPolicy {
+    pub initial: u64,
+    pub multiplier: f64,
+    pub max: u64,
+    pub jitter: f64,
+    /// Failed attempts in a row before giving up until the config or the
+    /// network changes, 0 never gives up.
+    pub max_attempts: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial: 30,
+            multiplier: 2.0,
+            max: 900,
+            jitter: 0.1,
+            max_attempts: 0,
+        }
+    }
 }
 
+impl RetryPolicy {
+    /// The wait after `failures` failed attempts in a row.
+    pub fn delay(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let wait =
+            (self.initial as f64 * self.multiplier.max(1.0).powi(exponent)).min(self.max as f64);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        let factor = if jitter > 0.0 {
+            1.0 + rand::thread_rng().gen_range(-jitter..=jitter)
+        } else {
+            1.0
+        };
+        // a `max` near u64::MAX plus jitter is more than a Duration holds
+        Duration::try_from_secs_f64(wait * factor).unwrap_or(Duration::MAX)
+    }
+
+    /// Whether `failures` failed attempts in a row are all we make.
+    pub fn exhausted(&self, failures: u32) -> bool {
+        self.max_attempts != 0 && failures >= self.max_attempts
+    }
+}
+
+/// A connectivity check URL and what it answers with when nothing is in the way.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ProbeEndpoint {
+    pub url: String,
+    #[serde(default = "default_probe_status")]
+    pub status: u16,
+    /// Text the response body has to contain, if the status alone isn't enough.
+    pub body: Option<String>,
+}
+
+/// How the votes of the probe endpoints are turned into a verdict.
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VotePolicy {
+    /// A single endpoint seeing a portal is enough.
+    Any,
+    /// Captive when at least as many endpoints see a portal as see the internet.
+    #[default]
+    Majority,
+    /// Captive only when no reachable endpoint sees the internet.
+    All,
+}
+
+fn default_keepalive() -> u64 {
+    300
+}
 
+fn default_watched_refresh() -> u64 {
+    300
+}
+
+fn default_probe_status() -> u16 {
+    200
+}
+
+fn default_probes() -> Vec<ProbeEndpoint> {
+    let probe = |url: &str, status: u16, body: Option<&str>| ProbeEndpoint {
+        url: url.to_string(),
+        status,
+        body: body.map(str::to_string),
+    };
+    vec![
+        probe(
+            "http://connectivitycheck.gstatic.com/generate_204",
+            204,
+            None,
+        ),
+        probe(
+            "http://captive.apple.com/hotspot-detect.html",
+            200,
+            Some("Success"),
+        ),
+        probe(
+            "http://www.msftconnecttest.com/connecttest.txt",
+            200,
+            Some("Microsoft Connect Test"),
+        ),
+        probe(
+            "http://detectportal.firefox.com/success.txt",
+            200,
+            Some("success"),
+        ),
+    ]
+}
+
+impl Config {
+    /// The first profile matching the network, falling back to `profile`.
+    /// Returns the profile's name along with it.
+    pub fn profile_for(
+        &self,
+        link: &LinkInfo,
+        portal_host: Option<&str>,
+    ) -> Option<(&str, &Profile)> {
+        self.profiles
+            .iter()
+            .find(|profile| profile.rules.matches(link, portal_host))
+            .map(|profile| (profile.name.as_str(), &profile.profile))
+            .or_else(|| self.profile.as_ref().map(|profile| ("default", profile)))
+    }
+
+    /// Directory holding the user's TOML portal definitions.
+    pub fn portals_dir(&self) -> PathBuf {
+        self.portals_dir
+            .clone()
+            .unwrap_or_else(|| home_dir().join(".portalkombatd").join("portals"))
+    }
+}
+
+/// Reads `~/.portalkombatd.toml`.
+pub fn load() -> anyhow::Result<Config> {
+    let path = home_dir().join(".portalkombatd.toml");
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| anyhow::anyhow!("can't read {}: {}", path.display(), e))?;
+    toml::from_str(&content).map_err(|e| anyhow::anyhow!("can't parse {}: {}", path.display(), e))
+}
+
+pub fn home_dir() -> PathBuf {
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    let home_str = std::env::var("HOME").expect("HOME env variable not set");
+
+    #[cfg(target_os = "windows")]
+    let home_str = std::env::var("USERPROFILE").expect("USERPROFILE env variable not set");
+    PathBuf::from(home_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            initial: 10,
+            multiplier: 2.0,
+            max: 100,
+            jitter,
+            max_attempts: 3,
+        }
+    }
+
+    #[test]
+    fn delay_grows_up_to_max() {
+        let policy = policy(0.0);
+        let delays: Vec<u64> = (1..=6).map(|n| policy.delay(n).as_secs()).collect();
+        assert_eq!(delays, [10, 20, 40, 80, 100, 100]);
+        // no failures yet waits as long as the first one
+        assert_eq!(policy.delay(0).as_secs(), 10);
+        assert_eq!(policy.delay(u32::MAX).as_secs(), 100);
+    }
+
+    #[test]
+    fn delay_stays_within_jitter() {
+        let policy = policy(0.1);
+        for failures in 1..=6 {
+            let wait = policy.delay(failures).as_secs_f64();
+            let expected = (10.0 * 2f64.powi(failures as i32 - 1)).min(100.0);
+            assert!(
+                (expected * 0.9..=expected * 1.1).contains(&wait),
+                "{} after {} failures",
+                wait,
+                failures
+            );
+        }
+    }
+
+    #[test]
+    fn delay_saturates_past_duration_max() {
+        let policy = RetryPolicy {
+            initial: u64::MAX,
+            max: u64::MAX,
+            jitter: 0.5,
+            ..policy(0.0)
+        };
+        for failures in 1..=10 {
+            assert!(policy.delay(failures) >= Duration::from_secs(u64::MAX / 2));
+        }
+    }
+
+    #[test]
+    fn exhausted_after_max_attempts() {
+        let policy = policy(0.0);
+        assert!(!policy.exhausted(0));
+        assert!(!policy.exhausted(2));
+        assert!(policy.exhausted(3));
+        assert!(policy.exhausted(4));
+
+        let forever = RetryPolicy {
+            max_attempts: 0,
+            ..policy
+        };
+        assert!(!forever.exhausted(0));
+        assert!(!forever.exhausted(u32::MAX));
+    }
+
+    #[test]
+    fn event_policies_build_on_the_builtin_ones() {
+        let retry: RetryPolicies = toml::from_str(
+            "[default]\nmultiplier = 3.0\n\n[WrongCreds]\ninitial = 120\n\n[Timeout]\nmax_attempts = 5\njitter = 0.0",
+        )
+        .unwrap();
+        let wrong_creds = retry.for_event(Event::WrongCreds);
+        assert_eq!(wrong_creds.initial, 120);
+        assert_eq!(wrong_creds.max_attempts, 1);
+        assert_eq!(wrong_creds.multiplier, 3.0);
+
+        let timeout = retry.for_event(Event::Timeout);
+        assert_eq!(timeout.max_attempts, 5);
+        assert_eq!(timeout.jitter, 0.0);
+        assert_eq!(timeout.initial, 30);
+        assert_eq!(timeout.multiplier, 3.0);
+
+        let max_concurrent = retry.for_event(Event::MaxConcurrent);
+        assert_eq!((max_concurrent.initial, max_concurrent.max), (300, 3600));
+        assert_eq!(max_concurrent.max_attempts, 0);
+
+        let none = RetryPolicies::default();
+        assert_eq!(none.for_event(Event::WrongCreds).max_attempts, 1);
+        assert_eq!(none.for_event(Event::Unknown).initial, 30);
+    }
+
+    fn link() -> LinkInfo {
+        LinkInfo {
+            ssid: Some("CampusNet".to_string()),
+            bssid: Some("00:1A:2b:3C:4d:5E".to_string()),
+            gateway: Some("10.0.0.1".parse().unwrap()),
+            gateway_mac: Some("aa:bb:cc:dd:ee:ff".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn matches_rules() {
+        let cases = [
+            // no rules at all match every network
+            ("", None, true),
+            ("ssid = [\"CampusNet\"]", None, true),
+            ("ssid = [\"campusnet\"]", None, false),
+            ("ssid = [\"Guest\", \"CampusNet\"]", None, true),
+            ("bssid = [\"00-1a-2B-3c-4D-5e\"]", None, true),
+            ("bssid = [\"00:1a:2b:3c:4d:5f\"]", None, false),
+            ("gateway_mac = [\"AA-BB-CC-DD-EE-FF\"]", None, true),
+            ("gateway_ip = [\"10.0.0.1\"]", None, true),
+            ("gateway_ip = [\"10.0.0.254\"]", None, false),
+            // every rule that is set has to match
+            (
+                "ssid = [\"CampusNet\"]\ngateway_ip = [\"10.0.0.254\"]",
+                None,
+                false,
+            ),
+            (
+                "portal_host = [\"campus.edu\"]",
+                Some("login.CAMPUS.edu"),
+                true,
+            ),
+            ("portal_host = [\"campus.edu\"]", Some("campus.edu"), true),
+            (
+                "portal_host = [\"campus.edu\"]",
+                Some("notcampus.edu"),
+                false,
+            ),
+            // a rule on something we don't know yet doesn't match
+            ("portal_host = [\"campus.edu\"]", None, false),
+        ];
+        for (rules, portal_host, expected) in cases {
+            let rules: MatchRules = toml::from_str(rules).unwrap();
+            assert_eq!(
+                rules.matches(&link(), portal_host),
+                expected,
+                "{:?} {:?}",
+                rules,
+                portal_host
+            );
+        }
+        let unknown = LinkInfo::default();
+        let rules: MatchRules = toml::from_str("bssid = [\"00:1a:2b:3c:4d:5e\"]").unwrap();
+        assert!(!rules.matches(&unknown, None));
+    }
+
+    #[test]
+    fn picks_the_first_matching_profile() {
+        let profiles = r#"
+            [[profiles]]
+            name = "hotel"
+            voucher = "1234"
+            [profiles.match]
+            ssid = ["Hotel"]
+
+            [[profiles]]
+            name = "campus"
+            rollno = "jdoe"
+            password = "secret"
+            [profiles.match]
+            gateway_ip = ["10.0.0.1"]
+
+            [[profiles]]
+            name = "anywhere"
+            accept_terms = true
+        "#;
+        let config = |extra: &str| -> Config {
+            toml::from_str(&format!(
+                "refresh = 60\ntimeouts = 5\n{}\n{}",
+                extra, profiles
+            ))
+            .unwrap()
+        };
+        let name = |config: &Config, link: &LinkInfo| {
+            config
+                .profile_for(link, None)
+                .map(|(name, _)| name.to_string())
+        };
+
+        let config = config("");
+        assert_eq!(name(&config, &link()).as_deref(), Some("campus"));
+        let hotel = LinkInfo {
+            ssid: Some("Hotel".to_string()),
+            ..link()
+        };
+        // both "hotel" and "campus" match, the first one listed wins
+        assert_eq!(name(&config, &hotel).as_deref(), Some("hotel"));
+        assert_eq!(
+            name(&config, &LinkInfo::default()).as_deref(),
+            Some("anywhere")
+        );
+    }
+
+    #[test]
+    fn skips_networks_no_profile_matches() {
+        let profiles = r#"
+            [[profiles]]
+            name = "campus"
+            rollno = "jdoe"
+            password = "secret"
+            [profiles.match]
+            ssid = ["CampusNet"]
+        "#;
+        let base = "refresh = 60\ntimeouts = 5\n";
+        let config: Config = toml::from_str(&format!("{}{}", base, profiles)).unwrap();
+        let elsewhere = LinkInfo {
+            ssid: Some("Cafe".to_string()),
+            ..link()
+        };
+        assert!(config.profile_for(&elsewhere, None).is_none());
+        assert_eq!(
+            config.profile_for(&link(), None).map(|(name, _)| name),
+            Some("campus")
+        );
+
+        let with_default: Config = toml::from_str(&format!(
+            "{}[profile]\nrollno = \"guest\"\npassword = \"guest\"\n{}",
+            base, profiles
+        ))
+        .unwrap();
+        assert_eq!(
+            with_default
+                .profile_for(&elsewhere, None)
+                .map(|(name, _)| name),
+            Some("default")
+        );
+    }
+}
@@ -0,0 +1,21 @@
+use tokio::sync::oneshot;
+
+use crate::event::Event;
+
+/// Requests the API server and the network watcher hand over to the task
+/// running the state machine. The status and the history are read from what
+/// the machine publishes instead, see `Machine::subscribe`.
+pub enum Command {
+    /// End the current portal session and report how it went.
+    Logout(oneshot::Sender<Event>),
+    /// Read the config file again, reporting why it couldn't be.
+    Reload(oneshot::Sender<Result<(), String>>),
+    /// Stop or resume handling the network.
+    SetEnabled(bool),
+    /// A link, address or route changed, check again soon.
+    NetworkChanged,
+    /// Network changes are no longer reported, poll as if they never were.
+    WatchLost,
+    /// Log out and stop the daemon.
+    Shutdown,
+}
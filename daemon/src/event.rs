@@ -1,9 +1,25 @@
-#[derive(Debug)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Event {
     // START,
     MaxConcurrent,
     WrongCreds,
     Success,
+    KeepAlive,
+    SessionExpired,
+    LoggedOut,
+    LogoutFailed,
+    /// A portal no driver can log into.
+    NoDriver,
+    // talking to the portal failed, see `CaptiveError`
+    Timeout,
+    TlsError,
+    DnsError,
+    ConnectionFailed,
+    UnexpectedStatus,
+    UnparseablePage,
+    MissingForm,
     // VPN,
     // INTERNET_AVAILABLE,
     Unknown,
@@ -1,9 +1,19 @@
 use crate::captive::Captive;
+use crate::captive::PortalStatus;
+use crate::captive::detect::Verdict;
 use crate::configs::Config;
 use crate::event::Event;
-use crate::platform::NetworkManager;
+use crate::platform::{LinkInfo, NetworkManager};
+use async_trait::async_trait;
+use log::{debug, error, info, warn};
+use serde::{Serialize, Serializer};
 use std::any::Any;
 use std::boxed::Box;
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tokio::time::timeout;
 
 #[cfg(target_os = "macos")]
 use crate::platform::macos::MacOSNetworkManager;
@@ -14,52 +24,231 @@ use crate::platform::linux::LinuxNetworkManager;
 #[cfg(target_os = "windows")]
 use crate::platform::windows::WindowsNetworkManager;
 
+/// How often a login that failed to reach the portal is retried right away
+/// before backing off.
+const IMMEDIATE_RETRIES: u32 = 2;
+
+/// Transitions one check may take before it is cut short. The longest path,
+/// a login retried right away twice out of `Backoff`, takes 10.
+const MAX_TRANSITIONS: usize = 16;
+
+/// How long one check may run before it is cut short.
+const CHECK_DEADLINE: Duration = Duration::from_secs(180);
+
+/// Transitions kept for the control API. Checks that end where they started
+/// without hearing from the portal aren't kept, so polling an open network
+/// doesn't crowd out a failure from overnight.
+const HISTORY_LEN: usize = 256;
+
 struct Context {
     pub config: Config,
     pub nm: Box<dyn NetworkManager>,
     pub captive: Captive,
+    /// Logins retried in a row because the portal couldn't be reached.
+    pub login_retries: u32,
+    /// Logins that failed in a row, for the backoff.
+    pub failures: u32,
+    /// The link as of the last check.
+    pub link: Option<LinkInfo>,
+    /// What the portal answered in the state being left, for its transition.
+    pub event: Option<Event>,
+    /// The profile matched for the current login attempt and the portal it
+    /// logs into, until the machine is idle again.
+    pub profile: Option<String>,
+    pub portal_url: Option<String>,
+}
+
+impl Context {
+    /// Asks for the link again. Returns whether we are on another network
+    /// than at the last check.
+    async fn refresh_link(&mut self) -> bool {
+        let link = self.nm.link_info().await;
+        let changed = !same_network(link.as_ref(), self.link.as_ref());
+        if changed {
+            match &link {
+                Some(link) => info!("connected to {}", link),
+                None => info!("no network link"),
+            }
+        }
+        self.link = link;
+        changed
+    }
+}
+
+/// Whether two links reach the same network. The access point and the
+/// signal change as we roam, the interface, SSID and gateway don't.
+fn same_network(a: Option<&LinkInfo>, b: Option<&LinkInfo>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            a.interface == b.interface && a.ssid == b.ssid && a.gateway == b.gateway
+        }
+        (None, None) => true,
+        _ => false,
+    }
 }
 
-trait State: Any {
+#[async_trait]
+trait State: Any + Send {
     fn on_enter(&mut self, _ctx: &mut Context) {}
     fn on_exit(&mut self, _ctx: &mut Context) {}
-    fn handle(&mut self, _ctx: &mut Context) -> Option<Box<dyn State>>;
+    /// `None` stays in this state and ends the check.
+    async fn handle(&mut self, _ctx: &mut Context) -> Option<Box<dyn State>>;
     fn name(&self) -> &'static str;
+    /// Whether a check ends once it gets here. The next one starts here.
+    fn is_resting(&self) -> bool {
+        false
+    }
+    fn status(&self) -> StateStatus {
+        StateStatus::Checking
+    }
 
     fn as_any(&self) -> &dyn Any;
 }
 
+/// One transition of the state machine.
+#[derive(Debug, Clone, Serialize)]
+pub struct TransitionRecord {
+    /// Seconds since the Unix epoch.
+    pub at: u64,
+    pub from: &'static str,
+    pub to: &'static str,
+    /// Time spent in `from` before it handed over.
+    pub duration_ms: u64,
+    /// What the portal answered, when that is what led to `to`.
+    pub event: Option<Event>,
+    pub profile: Option<String>,
+    pub portal_url: Option<String>,
+}
+
+impl fmt::Display for TransitionRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {} after {}ms",
+            self.from, self.to, self.duration_ms
+        )?;
+        if let Some(event) = &self.event {
+            write!(f, " on {:?}", event)?;
+        }
+        Ok(())
+    }
+}
+
+/// The state the machine rests in, for the control API.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(tag = "name", rename_all = "snake_case")]
+pub enum StateStatus {
+    #[default]
+    Idle,
+    /// A check is running.
+    Checking,
+    Authenticated {
+        #[serde(flatten)]
+        session: SessionInfo,
+    },
+    Backoff {
+        event: Event,
+        failures: u32,
+        /// Seconds until the next attempt, `None` once the retry policy gave
+        /// up.
+        #[serde(rename = "retry_in", serialize_with = "seconds_until")]
+        until: Option<Instant>,
+    },
+    CredentialsRejected {
+        profile: String,
+    },
+    LoggedOut,
+    Disabled,
+}
+
+/// The portal session we opened.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    /// The profile we logged in with.
+    pub profile: String,
+    pub portal_host: Option<String>,
+    /// Seconds since the login.
+    #[serde(rename = "seconds", serialize_with = "seconds_since")]
+    pub since: Instant,
+}
+
+/// Status is read after it was published, times are counted to the moment
+/// it is serialized.
+fn seconds_since<S: Serializer>(since: &Instant, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(since.elapsed().as_secs())
+}
+
+fn seconds_until<S: Serializer>(until: &Option<Instant>, serializer: S) -> Result<S::Ok, S::Error> {
+    until
+        .map(|until| until.saturating_duration_since(Instant::now()).as_secs())
+        .serialize(serializer)
+}
+
 struct Idle;
 struct AdapterOn;
+struct Probing;
 struct OnLoginPage;
+struct Authenticated {
+    session: SessionInfo,
+}
+struct KeepAlive {
+    session: SessionInfo,
+}
+struct Backoff {
+    /// What the last login attempt ended with.
+    event: Event,
+    /// Logins that failed in a row, this one included.
+    failures: u32,
+    /// When to try again, `None` when the retry policy gave up.
+    until: Option<Instant>,
+}
+struct CredentialsRejected {
+    profile: String,
+}
+struct LoggedOut;
+struct Disabled;
 // struct Notify;
 
+#[async_trait]
 impl State for Idle {
     fn name(&self) -> &'static str {
         "Idle"
     }
-    fn handle(&mut self, _ctx: &mut Context) -> Option<Box<dyn State>> {
-        match _ctx.nm.is_adapater_on() {
-            Ok(true) => return Some(Box::new(AdapterOn)),
-            Ok(false) => return Some(Box::new(Idle)),
+    fn on_enter(&mut self, _ctx: &mut Context) {
+        _ctx.failures = 0;
+        _ctx.login_retries = 0;
+        _ctx.profile = None;
+        _ctx.portal_url = None;
+    }
+    async fn handle(&mut self, _ctx: &mut Context) -> Option<Box<dyn State>> {
+        match _ctx.nm.is_adapater_on().await {
+            Ok(true) => Some(Box::new(AdapterOn)),
             _ => None,
         }
     }
+    fn is_resting(&self) -> bool {
+        true
+    }
+    fn status(&self) -> StateStatus {
+        StateStatus::Idle
+    }
     fn as_any(&self) -> &dyn Any {
         self
     }
 }
+#[async_trait]
 impl State for AdapterOn {
     fn name(&self) -> &'static str {
         "Wifi On"
     }
-    fn handle(&mut self, _ctx: &mut Context) -> Option<Box<dyn State>> {
-        if _ctx.nm.internet_available(_ctx.config.timeouts) {
+    async fn handle(&mut self, _ctx: &mut Context) -> Option<Box<dyn State>> {
+        _ctx.refresh_link().await;
+        let advertised = _ctx.nm.captive_portal_uri().await;
+        _ctx.captive.set_advertised_capport(advertised);
+        if !_ctx.captive.has_capport() && _ctx.nm.internet_available(_ctx.config.timeouts).await {
             return Some(Box::new(Idle));
-        } else if _ctx.captive.probe() {
-            return Some(Box::new(OnLoginPage));
         }
-        return Some(Box::new(Idle));
+        Some(Box::new(Probing))
     }
 
     fn as_any(&self) -> &dyn Any {
@@ -67,17 +256,133 @@ impl State for AdapterOn {
     }
 }
 
+#[async_trait]
+impl State for Probing {
+    fn name(&self) -> &'static str {
+        "Probing"
+    }
+    async fn handle(&mut self, _ctx: &mut Context) -> Option<Box<dyn State>> {
+        match _ctx.captive.probe().await {
+            Verdict::Captive(_) => Some(Box::new(OnLoginPage)),
+            Verdict::Open | Verdict::Offline => Some(Box::new(Idle)),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[async_trait]
 impl State for OnLoginPage {
     fn name(&self) -> &'static str {
         "OnLoginPage"
     }
 
-    fn handle(&mut self, _ctx: &mut Context) -> Option<Box<dyn State>> {
-        match _ctx.captive.login(&_ctx.config.profile) {
-            Event::Success => Some(Box::new(Idle)),
-            Event::MaxConcurrent => Some(Box::new(Idle)),
-            Event::WrongCreds => Some(Box::new(Idle)),
-            Event::Unknown => Some(Box::new(Idle)),
+    async fn handle(&mut self, _ctx: &mut Context) -> Option<Box<dyn State>> {
+        let link = _ctx.link.clone().unwrap_or_default();
+        let portal_host = _ctx.captive.portal_host();
+        _ctx.portal_url = _ctx.captive.portal_url();
+        let Some((name, profile)) = _ctx.config.profile_for(&link, portal_host.as_deref()) else {
+            info!(
+                "no profile matches {} behind {:?}, leaving it alone",
+                link, portal_host
+            );
+            return Some(Box::new(Idle));
+        };
+        info!("logging in with the '{}' profile", name);
+        let name = name.to_string();
+        _ctx.profile = Some(name.clone());
+        let event = _ctx.captive.login(profile).await.unwrap_or_else(|e| {
+            error!("login failed: {}", e);
+            Event::from(&e)
+        });
+        _ctx.event = Some(event);
+        match event {
+            Event::Success => Some(Box::new(Authenticated {
+                session: SessionInfo {
+                    profile: name,
+                    portal_host,
+                    since: Instant::now(),
+                },
+            })),
+            // the same credentials will be turned down again
+            Event::WrongCreds
+                if _ctx
+                    .config
+                    .retry
+                    .for_event(Event::WrongCreds)
+                    .exhausted(_ctx.failures + 1) =>
+            {
+                Some(Box::new(CredentialsRejected { profile: name }))
+            }
+            // the network may just be settling, try again before backing off
+            Event::Timeout | Event::DnsError | Event::ConnectionFailed
+                if _ctx.login_retries < IMMEDIATE_RETRIES =>
+            {
+                _ctx.login_retries += 1;
+                Some(Box::new(AdapterOn))
+            }
+            event => Some(Box::new(Backoff::after(event))),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[async_trait]
+impl State for Authenticated {
+    fn name(&self) -> &'static str {
+        "Authenticated"
+    }
+    fn on_enter(&mut self, _ctx: &mut Context) {
+        _ctx.failures = 0;
+        _ctx.login_retries = 0;
+        debug!(
+            "authenticated with the '{}' profile at {:?}",
+            self.session.profile, self.session.portal_host
+        );
+    }
+    async fn handle(&mut self, _ctx: &mut Context) -> Option<Box<dyn State>> {
+        match _ctx.nm.is_adapater_on().await {
+            Ok(true) => {}
+            Ok(false) => return Some(Box::new(Idle)),
+            Err(_) => return None,
+        }
+        if _ctx.refresh_link().await {
+            info!("left the network the portal session is for");
+            return Some(Box::new(AdapterOn));
+        }
+        let advertised = _ctx.nm.captive_portal_uri().await;
+        _ctx.captive.set_advertised_capport(advertised);
+        if _ctx
+            .captive
+            .keepalive_due(Duration::from_secs(_ctx.config.keepalive))
+        {
+            return Some(Box::new(KeepAlive {
+                session: self.session.clone(),
+            }));
+        }
+        if !_ctx.captive.has_capport() && _ctx.nm.internet_available(_ctx.config.timeouts).await {
+            return None;
+        }
+        match _ctx.captive.probe().await {
+            Verdict::Open => None,
+            Verdict::Captive(_) => {
+                info!("the portal session is gone, logging in again");
+                Some(Box::new(OnLoginPage))
+            }
+            Verdict::Offline => Some(Box::new(Idle)),
+        }
+    }
+    fn is_resting(&self) -> bool {
+        true
+    }
+    fn status(&self) -> StateStatus {
+        StateStatus::Authenticated {
+            session: self.session.clone(),
         }
     }
 
@@ -86,57 +391,470 @@ impl State for OnLoginPage {
     }
 }
 
+#[async_trait]
+impl State for KeepAlive {
+    fn name(&self) -> &'static str {
+        "KeepAlive"
+    }
+
+    async fn handle(&mut self, _ctx: &mut Context) -> Option<Box<dyn State>> {
+        let session = self.session.clone();
+        match _ctx.captive.keepalive().await {
+            Ok(event) => {
+                _ctx.event = Some(event);
+                match event {
+                    Event::KeepAlive => Some(Box::new(Authenticated { session })),
+                    // the session is gone, probe again so we log in with a fresh portal URL
+                    _ => Some(Box::new(AdapterOn)),
+                }
+            }
+            // the session may still be there, try again on the next check
+            Err(e) => {
+                error!("keepalive failed: {}", e);
+                _ctx.event = Some(Event::from(&e));
+                Some(Box::new(Authenticated { session }))
+            }
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Backoff {
+    fn after(event: Event) -> Self {
+        Self {
+            event,
+            failures: 0,
+            until: None,
+        }
+    }
+}
+
+#[async_trait]
+impl State for Backoff {
+    fn name(&self) -> &'static str {
+        "Backoff"
+    }
+    fn on_enter(&mut self, _ctx: &mut Context) {
+        _ctx.failures += 1;
+        _ctx.login_retries = 0;
+        self.failures = _ctx.failures;
+        let policy = _ctx.config.retry.for_event(self.event);
+        if policy.exhausted(self.failures) {
+            warn!(
+                "login failed with {:?} {} time(s) in a row, giving up until the config or the network changes",
+                self.event, self.failures
+            );
+            return;
+        }
+        let delay = policy.delay(self.failures);
+        // a wait past what an Instant holds is as good as giving up
+        self.until = Instant::now().checked_add(delay);
+        warn!(
+            "login failed with {:?} {} time(s) in a row, trying again in {}s",
+            self.event,
+            self.failures,
+            delay.as_secs()
+        );
+    }
+    async fn handle(&mut self, _ctx: &mut Context) -> Option<Box<dyn State>> {
+        if _ctx.refresh_link().await {
+            _ctx.failures = 0;
+            return Some(Box::new(AdapterOn));
+        }
+        match self.until {
+            Some(until) if Instant::now() >= until => Some(Box::new(AdapterOn)),
+            _ => None,
+        }
+    }
+    fn is_resting(&self) -> bool {
+        true
+    }
+    fn status(&self) -> StateStatus {
+        StateStatus::Backoff {
+            event: self.event,
+            failures: self.failures,
+            until: self.until,
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[async_trait]
+impl State for CredentialsRejected {
+    fn name(&self) -> &'static str {
+        "CredentialsRejected"
+    }
+    fn on_enter(&mut self, _ctx: &mut Context) {
+        warn!(
+            "the portal rejected the '{}' profile, not trying it again until the config changes",
+            self.profile
+        );
+    }
+    fn on_exit(&mut self, _ctx: &mut Context) {
+        _ctx.failures = 0;
+        _ctx.login_retries = 0;
+    }
+    async fn handle(&mut self, _ctx: &mut Context) -> Option<Box<dyn State>> {
+        // the credentials may well work on the next network
+        if _ctx.refresh_link().await {
+            return Some(Box::new(AdapterOn));
+        }
+        None
+    }
+    fn is_resting(&self) -> bool {
+        true
+    }
+    fn status(&self) -> StateStatus {
+        StateStatus::CredentialsRejected {
+            profile: self.profile.clone(),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[async_trait]
+impl State for LoggedOut {
+    fn name(&self) -> &'static str {
+        "LoggedOut"
+    }
+    fn on_enter(&mut self, _ctx: &mut Context) {
+        info!("logged out, not logging in again until enabled, reloaded or the network changes");
+    }
+    async fn handle(&mut self, _ctx: &mut Context) -> Option<Box<dyn State>> {
+        if _ctx.refresh_link().await {
+            return Some(Box::new(AdapterOn));
+        }
+        None
+    }
+    fn is_resting(&self) -> bool {
+        true
+    }
+    fn status(&self) -> StateStatus {
+        StateStatus::LoggedOut
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[async_trait]
+impl State for Disabled {
+    fn name(&self) -> &'static str {
+        "Disabled"
+    }
+    fn on_enter(&mut self, _ctx: &mut Context) {
+        info!("disabled, leaving the network alone until enabled again");
+    }
+    fn on_exit(&mut self, _ctx: &mut Context) {
+        info!("enabled");
+    }
+    async fn handle(&mut self, _ctx: &mut Context) -> Option<Box<dyn State>> {
+        None
+    }
+    fn is_resting(&self) -> bool {
+        true
+    }
+    fn status(&self) -> StateStatus {
+        StateStatus::Disabled
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// What the daemon knows about the network it is on, for the control API.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MachineStatus {
+    pub state: StateStatus,
+    pub link: Option<LinkInfo>,
+    pub portal: PortalStatus,
+}
+
+/// What the machine last published for the control API, which reads it
+/// without waiting for a check to finish.
+#[derive(Debug, Default)]
+pub struct Published {
+    pub status: MachineStatus,
+    /// The last `HISTORY_LEN` transitions worth keeping, oldest first.
+    pub history: VecDeque<TransitionRecord>,
+}
+
 pub struct Machine {
     _ctx: Context,
     state: Box<dyn State>,
+    published: watch::Sender<Published>,
+    /// The transitions of the check in progress, kept once it is over.
+    pass: Option<Vec<TransitionRecord>>,
+    /// When the machine entered the state it is in.
+    entered: Instant,
 }
 impl Machine {
     pub fn new(config: Config) -> Self {
         #[cfg(target_os = "macos")]
-        return Self {
-            _ctx: Context {
-                captive: Captive::new(config.timeouts),
-                config: config,
-                nm: Box::new(MacOSNetworkManager::new()),
-            },
-            state: Box::new(Idle),
-        };
+        let nm = Box::new(MacOSNetworkManager::new());
         #[cfg(target_os = "linux")]
-        return Self {
-            _ctx: Context {
-                captive: Captive::new(config.timeouts),
-                config: config,
-                nm: Box::new(LinuxNetworkManager::new()),
-            },
-            state: Box::new(Idle),
-        };
+        let nm = Box::new(LinuxNetworkManager::new());
         #[cfg(target_os = "windows")]
-        return Self {
+        let nm = Box::new(WindowsNetworkManager::new());
+        Self::with_network_manager(config, nm)
+    }
+
+    fn with_network_manager(config: Config, nm: Box<dyn NetworkManager>) -> Self {
+        Self {
             _ctx: Context {
-                captive: Captive::new(config.timeouts),
-                config: config,
-                nm: Box::new(WindowsNetworkManager::new()),
+                captive: Captive::new(&config),
+                config,
+                login_retries: 0,
+                failures: 0,
+                link: None,
+                event: None,
+                profile: None,
+                portal_url: None,
+                nm,
             },
             state: Box::new(Idle),
+            published: watch::Sender::new(Published::default()),
+            pass: None,
+            entered: Instant::now(),
+        }
+    }
+
+    /// Ends the portal session. Logged out, the machine stays put until
+    /// enabled, reloaded or the network changes, so that it doesn't log
+    /// straight back in.
+    pub async fn logout(&mut self) -> Event {
+        let event = self._ctx.captive.logout().await.unwrap_or_else(|e| {
+            error!("logout failed: {}", e);
+            Event::from(&e)
+        });
+        if self.state.as_any().is::<Disabled>() {
+            return event;
+        }
+        self._ctx.event = Some(event);
+        if matches!(event, Event::LoggedOut) {
+            self.enter(Box::new(LoggedOut));
+        } else if let Some(authenticated) = self.state.as_any().downcast_ref::<Authenticated>() {
+            // the session carries on
+            let session = authenticated.session.clone();
+            self.enter(Box::new(Authenticated { session }));
+        } else {
+            self._ctx.event = None;
+        }
+        event
+    }
+
+    pub fn config(&self) -> &Config {
+        &self._ctx.config
+    }
+
+    /// Whether there is a portal session to log out of.
+    pub fn has_session(&self) -> bool {
+        self._ctx.captive.has_session()
+    }
+
+    /// Where the control API reads the status and the history.
+    pub fn subscribe(&self) -> watch::Receiver<Published> {
+        self.published.subscribe()
+    }
+
+    fn publish(&self) {
+        let status = MachineStatus {
+            state: self.state.status(),
+            link: self._ctx.link.clone(),
+            portal: self._ctx.captive.status(),
         };
+        self.published
+            .send_modify(|published| published.status = status);
+    }
+
+    /// When the next check is due at the latest, if the portal, its keepalive
+    /// or the backoff say so.
+    pub fn next_check(&self) -> Option<Duration> {
+        let keepalive = self
+            ._ctx
+            .captive
+            .keepalive_in(Duration::from_secs(self._ctx.config.keepalive));
+        let backoff = self
+            .state
+            .as_any()
+            .downcast_ref::<Backoff>()
+            .and_then(|backoff| backoff.until)
+            .map(|until| until.saturating_duration_since(Instant::now()));
+        self._ctx
+            .captive
+            .relogin_in()
+            .into_iter()
+            .chain(keepalive)
+            .chain(backoff)
+            .min()
+    }
+
+    /// Takes a new config. Rejected credentials, failed logins and a logout
+    /// get another chance with it.
+    pub fn reload(&mut self, config: Config) {
+        info!("config reloaded");
+        self._ctx.captive.reconfigure(&config);
+        self._ctx.config = config;
+        self.publish();
+        if self.state.as_any().is::<CredentialsRejected>()
+            || self.state.as_any().is::<Backoff>()
+            || self.state.as_any().is::<LoggedOut>()
+        {
+            self.enter(Box::new(Idle));
+        }
     }
 
-    pub fn reset(&mut self) {
-        self.state = Box::new(Idle);
-        self.dispatch();
+    /// Disabled, the machine stays put until enabled again. Enabling also
+    /// ends a logout.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        let disabled = self.state.as_any().is::<Disabled>();
+        if enabled && (disabled || self.state.as_any().is::<LoggedOut>()) {
+            self.enter(Box::new(Idle));
+        } else if !enabled && !disabled {
+            self.enter(Box::new(Disabled));
+        }
     }
 
-    pub fn dispatch(&mut self) {
-        print!("{} -> ", self.state.name());
-        if let Some(mut new_state) = self.state.handle(&mut self._ctx) {
-            self.state.on_exit(&mut self._ctx);
-            new_state.on_enter(&mut self._ctx);
-            self.state = new_state;
-            if self.state.as_any().is::<Idle>() {
-                println!("end;");
-                return;
+    /// Runs the machine from the state the last check left it in until it
+    /// rests again. A check that takes more than `MAX_TRANSITIONS` or runs
+    /// past `CHECK_DEADLINE` is cut short and the next one starts over.
+    pub async fn check(&mut self) {
+        let (from, entered) = (self.state.name(), self.entered);
+        self.pass = Some(Vec::new());
+        let rested = self.run().await;
+        // the link and the portal may have changed without a transition
+        self.publish();
+        let pass = self.pass.take().unwrap_or_default();
+        // a keepalive that went through is just as routine
+        let routine = pass
+            .iter()
+            .all(|record| record.event.is_none_or(|event| event == Event::KeepAlive));
+        if rested && self.state.name() == from && routine {
+            // as far as the history goes the machine never left
+            self.entered = entered;
+            return;
+        }
+        for record in pass {
+            self.keep(record);
+        }
+    }
+
+    /// Whether the machine came to rest rather than being cut short.
+    async fn run(&mut self) -> bool {
+        let deadline = Instant::now() + CHECK_DEADLINE;
+        for _ in 0..MAX_TRANSITIONS {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let new_state = match timeout(left, self.state.handle(&mut self._ctx)).await {
+                Ok(Some(new_state)) => new_state,
+                Ok(None) => return true,
+                Err(_) => {
+                    warn!(
+                        "{} ran past the deadline of the check, starting over",
+                        self.state.name()
+                    );
+                    self.enter(Box::new(Idle));
+                    return false;
+                }
+            };
+            self.enter(new_state);
+            if self.state.is_resting() {
+                return true;
             }
-            self.dispatch();
         }
+        warn!(
+            "no rest after {} transitions, starting over from {}",
+            MAX_TRANSITIONS,
+            self.state.name()
+        );
+        self.enter(Box::new(Idle));
+        false
+    }
+
+    /// Leaves the current state for `new_state`.
+    fn enter(&mut self, mut new_state: Box<dyn State>) {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let record = TransitionRecord {
+            at: at.as_secs(),
+            from: self.state.name(),
+            to: new_state.name(),
+            duration_ms: self.entered.elapsed().as_millis() as u64,
+            event: self._ctx.event.take(),
+            profile: self._ctx.profile.clone(),
+            portal_url: self._ctx.portal_url.clone(),
+        };
+        info!("{}", record);
+        match &mut self.pass {
+            Some(pass) => pass.push(record),
+            None => self.keep(record),
+        }
+        self.state.on_exit(&mut self._ctx);
+        new_state.on_enter(&mut self._ctx);
+        self.state = new_state;
+        self.entered = Instant::now();
+        self.publish();
+    }
+
+    fn keep(&mut self, record: TransitionRecord) {
+        self.published.send_modify(|published| {
+            if published.history.len() == HISTORY_LEN {
+                published.history.pop_front();
+            }
+            published.history.push_back(record);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A network with the internet right there.
+    struct OpenNetwork;
+
+    #[async_trait]
+    impl NetworkManager for OpenNetwork {
+        async fn is_adapater_on(&self) -> anyhow::Result<bool> {
+            Ok(true)
+        }
+        async fn internet_available(&self, _time_out_seconds: u64) -> bool {
+            true
+        }
+        async fn link_info(&self) -> Option<LinkInfo> {
+            None
+        }
+    }
+
+    #[tokio::test]
+    async fn idle_polling_keeps_failures_in_history() {
+        let config = toml::from_str("refresh = 1\ntimeouts = 1").unwrap();
+        let mut m = Machine::with_network_manager(config, Box::new(OpenNetwork));
+        m._ctx.event = Some(Event::UnexpectedStatus);
+        m.enter(Box::new(Backoff::after(Event::UnexpectedStatus)));
+        m.set_enabled(false);
+        m.set_enabled(true);
+
+        for _ in 0..HISTORY_LEN * 2 {
+            m.check().await;
+        }
+
+        let published = m.subscribe();
+        let history = &published.borrow().history;
+        assert_eq!(history.len(), 3);
+        assert_eq!((history[0].from, history[0].to), ("Idle", "Backoff"));
+        assert_eq!(history[0].event, Some(Event::UnexpectedStatus));
+        assert_eq!(history[2].to, "Idle");
     }
 }
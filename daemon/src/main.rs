@@ -1,17 +1,20 @@
 mod api;
 mod captive;
 mod configs;
+mod control;
 mod event;
 mod fsm;
 mod platform;
 
 use configs::Config;
+use control::Command;
 use fsm::Machine;
 use log::info;
 
-use std::fs;
 use std::path::PathBuf;
 use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::time::{self, Instant};
 use crate::api::server::run_server;
 
 #[cfg(unix)]
@@ -19,61 +22,168 @@ use crate::api::platform::unix::UnixTransportListener;
 #[cfg(windows)]
 use crate::api::platform::windows::WindowsTransportListener;
 
-fn main() {
+/// Changes come in bursts, a link coming up is followed by its addresses and
+/// routes, so a check waits this long for the rest.
+const NETWORK_SETTLE: Duration = Duration::from_secs(1);
+
+#[tokio::main]
+async fn main() {
     env_logger::init();
     info!("starting wifi-captive-daemon");
-    #[cfg(any(target_os = "linux", target_os = "macos"))]
-    let home_str = std::env::var("HOME").expect("HOME env variable not set");
+    let config = configs::load().expect("Failed to load config file.");
+    let (commands, mut inbox) = mpsc::unbounded_channel::<Command>();
+
+    let on_change = commands.clone();
+    let on_lost = commands.clone();
+    let mut watching = platform::watch_changes(
+        move || {
+            let _ = on_change.send(Command::NetworkChanged);
+        },
+        move || {
+            let _ = on_lost.send(Command::WatchLost);
+        },
+    );
+    if watching {
+        info!("watching for network changes");
+    }
+    let mut poll_interval = poll_interval(&config, watching);
 
-    #[cfg(target_os = "windows")]
-    let home_str = std::env::var("USERPROFILE").expect("USERPROFILE env variable not set");
-    let mut config_path = PathBuf::from(home_str);
+    let on_shutdown = commands.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        info!("shutdown requested");
+        let _ = on_shutdown.send(Command::Shutdown);
+    });
 
-    config_path.push(".portalkombatd.toml");
-    let config_string = fs::read_to_string(config_path).expect("Failed to read config file.");
-    let config: Config = toml::from_str(&config_string).expect("Failed to parse config file.");
-    let poll_interval = Duration::from_secs(config.refresh);
-    
-    // Start API Server in a separate thread with its own Runtime
-    // This avoids conflict between reqwest::blocking (used in Machine) and tokio runtime.
-    std::thread::spawn(move || {
-        let rt = tokio::runtime::Builder::new_current_thread()
-            .enable_all()
-            .build()
-            .expect("Failed to build tokio runtime");
-        
-        rt.block_on(async {
-            #[cfg(unix)]
-            {
-                let socket_path = PathBuf::from("/tmp/portalkombat.sock");
-                match UnixTransportListener::bind(socket_path) {
-                    Ok(listener) => {
-                        if let Err(e) = run_server(listener).await {
-                            log::error!("API server error: {:?}", e);
-                        }
+    let mut m = Machine::new(config);
+    let published = m.subscribe();
+    tokio::spawn(async move {
+        #[cfg(unix)]
+        {
+            let socket_path = PathBuf::from("/tmp/portalkombat.sock");
+            match UnixTransportListener::bind(socket_path) {
+                Ok(listener) => {
+                    if let Err(e) = run_server(listener, commands, published).await {
+                        log::error!("API server error: {:?}", e);
                     }
-                    Err(e) => log::error!("Failed to bind socket: {:?}", e),
                 }
+                Err(e) => log::error!("Failed to bind socket: {:?}", e),
             }
+        }
 
-            #[cfg(windows)]
-            {
-                match WindowsTransportListener::bind("portalkombat") {
-                    Ok(listener) => {
-                        if let Err(e) = run_server(listener).await {
-                            log::error!("API server error: {:?}", e);
-                        }
+        #[cfg(windows)]
+        {
+            match WindowsTransportListener::bind("portalkombat") {
+                Ok(listener) => {
+                    if let Err(e) = run_server(listener, commands, published).await {
+                        log::error!("API server error: {:?}", e);
                     }
-                    Err(e) => log::error!("Failed to bind pipe: {:?}", e),
                 }
+                Err(e) => log::error!("Failed to bind pipe: {:?}", e),
             }
-        });
+        }
     });
 
-    let mut m = Machine::new(config);
+    let mut next_run = Instant::now();
+
+    loop {
+        let received = tokio::select! {
+            _ = time::sleep_until(next_run) => {
+                let held = check(&mut m, &mut inbox).await;
+                let interval = m
+                    .next_check()
+                    .filter(|due| !due.is_zero())
+                    .map_or(poll_interval, |due| due.min(poll_interval));
+                next_run = Instant::now() + interval;
+                held
+            }
+            Some(command) = inbox.recv() => vec![command],
+        };
+        for command in received {
+            match command {
+                Command::Logout(reply) => {
+                    let _ = reply.send(m.logout().await);
+                }
+                Command::Reload(reply) => {
+                    let result = configs::load().map(|config| {
+                        poll_interval = self::poll_interval(&config, watching);
+                        m.reload(config);
+                        next_run = Instant::now();
+                    });
+                    if let Err(e) = &result {
+                        log::error!("keeping the old config: {}", e);
+                    }
+                    let _ = reply.send(result.map_err(|e| e.to_string()));
+                }
+                Command::SetEnabled(enabled) => {
+                    m.set_enabled(enabled);
+                    next_run = Instant::now();
+                }
+                Command::NetworkChanged => {
+                    next_run = next_run.min(Instant::now() + NETWORK_SETTLE);
+                }
+                Command::WatchLost => {
+                    // changes since the watch ended went unnoticed
+                    watching = false;
+                    poll_interval = self::poll_interval(m.config(), watching);
+                    next_run = Instant::now();
+                }
+                Command::Shutdown => {
+                    if m.has_session() {
+                        info!("logging out before exit: {:?}", m.logout().await);
+                    }
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// With network changes reported, polling is only a safety net.
+fn poll_interval(config: &Config, watching: bool) -> Duration {
+    if watching {
+        Duration::from_secs(config.watched_refresh)
+    } else {
+        Duration::from_secs(config.refresh)
+    }
+}
 
+/// Runs one pass of the state machine. Commands that arrive meanwhile wait for
+/// it to finish, except a shutdown, which abandons the pass right away. The
+/// API server reads the status without a command, so it doesn't wait.
+async fn check(m: &mut Machine, inbox: &mut UnboundedReceiver<Command>) -> Vec<Command> {
+    let mut held = Vec::new();
+    let pass = m.check();
+    tokio::pin!(pass);
     loop {
-        m.reset();
-        std::thread::sleep(poll_interval);
+        tokio::select! {
+            _ = &mut pass => return held,
+            Some(command) = inbox.recv() => {
+                let shutdown = matches!(command, Command::Shutdown);
+                held.push(command);
+                if shutdown {
+                    info!("abandoning the check in progress");
+                    return held;
+                }
+            }
+        }
+    }
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut terminate =
+            signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(windows)]
+    {
+        let _ = tokio::signal::ctrl_c().await;
     }
 }
@@ -1,93 +1,757 @@
-use crate::platform::NetworkManager;
-use anyhow::{Result, anyhow};
+pub mod netlink;
+mod nm;
+
+use crate::platform::{self, LinkInfo, LinkKind, NetworkManager};
+use anyhow::Result;
+use async_trait::async_trait;
 use log::{debug, error, info, trace, warn};
-use std::process::Command;
-use std::time::Instant;
+use nm::Connectivity;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use tokio::sync::OnceCell;
+use tokio::time::{Duration, timeout};
+use zbus::Connection;
+
+/// Where dhclient keeps its leases, standalone or run by NetworkManager.
+const DHCLIENT_LEASE_DIRS: [&str; 3] = [
+    "/var/lib/NetworkManager",
+    "/var/lib/dhcp",
+    "/var/lib/dhclient",
+];
+
+/// RFC 8910 value for networks that advertise there is no portal.
+const CAPPORT_UNRESTRICTED: &str = "urn:ietf:params:capport:unrestricted";
 
-pub struct LinuxNetworkManager;
+/// Talks to NetworkManager over D-Bus. Without it, the kernel's routing
+/// table and the lease files of the DHCP clients are all there is to go by.
+pub struct LinuxNetworkManager {
+    bus: OnceCell<Connection>,
+}
 
 impl LinuxNetworkManager {
     pub fn new() -> Self {
-        Self
-    }
-    fn get_interface_details(&self) -> Result<(String, String, i32)> {
-        let start = Instant::now();
-        debug!("running: netsh wlan show interfaces");
-        let output = Command::new("nmcli").args(&["device", "status"]).output()?;
-        let duration = start.elapsed();
-        let code = output.status.code().unwrap_or(-1);
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-        debug!(
-            "netsh finished: exit={} time_ms={} stdout_len={} stderr_len={}",
-            code,
-            duration.as_millis(),
-            stdout.len(),
-            stderr.len()
-        );
-        trace!("netsh stdout:\n{}", stdout);
-        if !stderr.trim().is_empty() {
-            trace!("netsh stderr:\n{}", stderr);
+        Self {
+            bus: OnceCell::new(),
         }
-        Ok((stdout, stderr, code))
     }
 
-    fn parse_interfaces(&self, nmcli_out: &str) -> Option<Vec<(String, String)>> {
-        let mut interface_states: Vec<(String, String)> = Vec::new();
-        for line in nmcli_out.lines().skip(1) {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if matches!(parts[1], "wifi" | "ethernet") {
-                interface_states.push((parts[0].to_string(), parts[2].to_string()))
+    /// Uses `bus` to reach NetworkManager instead of the system bus.
+    #[cfg(test)]
+    pub fn with_connection(bus: Connection) -> Self {
+        Self {
+            bus: OnceCell::new_with(Some(bus)),
+        }
+    }
+
+    /// The system bus, connected to on first use. A failed attempt is
+    /// retried on the next call, the bus may come up after us.
+    async fn bus(&self) -> Option<&Connection> {
+        match self.bus.get_or_try_init(Connection::system).await {
+            Ok(bus) => Some(bus),
+            Err(e) => {
+                debug!("can't connect to the system bus: {}", e);
+                None
             }
         }
-        debug!("no 'Name' field found in netsh output");
-        if interface_states.is_empty() {
-            None
-        } else {
-            Some(interface_states)
+    }
+
+    /// The Wi-Fi and Ethernet devices NetworkManager knows, with their state.
+    async fn devices(
+        &self,
+        bus: &Connection,
+    ) -> zbus::Result<Vec<(nm::DeviceProxy<'static>, u32)>> {
+        let mut devices = Vec::new();
+        for path in nm::root(bus).await?.devices().await? {
+            let device: nm::DeviceProxy = nm::at(bus, path).await?;
+            if matches!(
+                device.device_type().await?,
+                nm::DEVICE_TYPE_ETHERNET | nm::DEVICE_TYPE_WIFI
+            ) {
+                let state = device.state().await?;
+                devices.push((device, state));
+            }
+        }
+        Ok(devices)
+    }
+
+    /// The device of NetworkManager's primary connection, the one holding
+    /// the default route, or else the first activated one.
+    async fn primary_device(
+        &self,
+        bus: &Connection,
+    ) -> zbus::Result<Option<nm::DeviceProxy<'static>>> {
+        let primary = nm::root(bus).await?.primary_connection().await?;
+        if nm::is_set(&primary) {
+            let active: nm::ActiveConnectionProxy = nm::at(bus, primary).await?;
+            if let Some(path) = active.devices().await?.into_iter().next() {
+                return Ok(Some(nm::at(bus, path).await?));
+            }
         }
+        Ok(self
+            .devices(bus)
+            .await?
+            .into_iter()
+            .find(|(_, state)| *state == nm::DEVICE_STATE_ACTIVATED)
+            .map(|(device, _)| device))
+    }
+
+    async fn nm_link_info(&self, bus: &Connection) -> zbus::Result<Option<LinkInfo>> {
+        let Some(device) = self.primary_device(bus).await? else {
+            return Ok(None);
+        };
+        let mut link = LinkInfo {
+            interface: device.interface().await?,
+            kind: match device.device_type().await? {
+                nm::DEVICE_TYPE_WIFI => LinkKind::Wifi,
+                nm::DEVICE_TYPE_ETHERNET => LinkKind::Ethernet,
+                _ => LinkKind::Other,
+            },
+            ..Default::default()
+        };
+        let ip4 = device.ip4_config().await?;
+        if nm::is_set(&ip4) {
+            let config: nm::IP4ConfigProxy = nm::at(bus, ip4).await?;
+            link.ipv4 = parse_addresses(nm::strings(&config.address_data().await?, "address"));
+            link.gateway = config.gateway().await?.parse().ok();
+            link.dns = parse_addresses(nm::strings(&config.nameserver_data().await?, "address"));
+        }
+        let ip6 = device.ip6_config().await?;
+        if nm::is_set(&ip6) {
+            let config: nm::IP6ConfigProxy = nm::at(bus, ip6).await?;
+            link.ipv6 = parse_addresses(nm::strings(&config.address_data().await?, "address"));
+            if link.gateway.is_none() {
+                link.gateway = config.gateway().await?.parse().ok();
+            }
+            link.dns.extend(
+                config
+                    .nameservers()
+                    .await?
+                    .into_iter()
+                    .filter_map(|bytes| <[u8; 16]>::try_from(bytes).ok())
+                    .map(|bytes| IpAddr::V6(Ipv6Addr::from(bytes))),
+            );
+        }
+        if link.kind == LinkKind::Wifi {
+            let wireless: nm::WirelessProxy =
+                nm::at(bus, device.inner().path().to_owned().into()).await?;
+            let access_point = wireless.active_access_point().await?;
+            if nm::is_set(&access_point) {
+                let access_point: nm::AccessPointProxy = nm::at(bus, access_point).await?;
+                link.ssid = Some(String::from_utf8_lossy(&access_point.ssid().await?).into_owned());
+                link.bssid = Some(access_point.hw_address().await?);
+                link.signal = Some(access_point.strength().await?);
+            }
+        }
+        Ok(Some(link))
+    }
+
+    /// What the kernel knows about the interface holding the default route.
+    fn kernel_link_info(&self) -> Option<LinkInfo> {
+        let routes = fs::read_to_string("/proc/net/route").ok()?;
+        let (interface, gateway) = parse_default_route(&routes)?;
+        let wireless = Path::new("/sys/class/net")
+            .join(&interface)
+            .join("wireless")
+            .exists();
+        Some(LinkInfo {
+            kind: if wireless {
+                LinkKind::Wifi
+            } else {
+                LinkKind::Other
+            },
+            interface,
+            gateway: gateway.map(IpAddr::V4),
+            ..Default::default()
+        })
+    }
+
+    /// Option 114 as NetworkManager's DHCP clients got it, for each activated
+    /// device.
+    async fn nm_captive_portals(
+        &self,
+        bus: &Connection,
+    ) -> zbus::Result<Vec<(String, Option<String>)>> {
+        let mut found = Vec::new();
+        for (device, state) in self.devices(bus).await? {
+            if state != nm::DEVICE_STATE_ACTIVATED {
+                continue;
+            }
+            let mut options = Vec::new();
+            let dhcp4 = device.dhcp4_config().await?;
+            if nm::is_set(&dhcp4) {
+                let config: nm::DHCP4ConfigProxy = nm::at(bus, dhcp4).await?;
+                options.extend(config.options().await?);
+            }
+            let dhcp6 = device.dhcp6_config().await?;
+            if nm::is_set(&dhcp6) {
+                let config: nm::DHCP6ConfigProxy = nm::at(bus, dhcp6).await?;
+                options.extend(config.options().await?);
+            }
+            let uri = options
+                .iter()
+                .find(|(name, _)| is_captive_portal_option(name))
+                .and_then(|(_, value)| nm::string(value));
+            found.push((device.interface().await?, uri));
+        }
+        Ok(found)
+    }
+
+    /// NetworkManager's verdict on the connection, `None` when it doesn't
+    /// check or can't tell.
+    async fn connectivity(&self, time_out_seconds: u64) -> Option<Connectivity> {
+        let root = nm::root(self.bus().await?).await.ok()?;
+        if !root.connectivity_check_enabled().await.unwrap_or(false) {
+            return None;
+        }
+        // left alone, NetworkManager only checks every few minutes
+        let limit = Duration::from_secs(time_out_seconds);
+        let state = match timeout(limit, root.check_connectivity()).await {
+            Ok(Ok(state)) => state,
+            Ok(Err(e)) => {
+                debug!("NetworkManager connectivity check failed: {}", e);
+                root.connectivity().await.ok()?
+            }
+            Err(_) => {
+                debug!("NetworkManager connectivity check timed out");
+                root.connectivity().await.ok()?
+            }
+        };
+        Some(Connectivity::from(state)).filter(|state| *state != Connectivity::Unknown)
+    }
+
+    /// systemd-networkd keeps the DHCP lease and the link state, RA options
+    /// included, in files named after the interface index.
+    fn networkd_captive_portal(&self, iface: &str) -> Option<String> {
+        let index = fs::read_to_string(format!("/sys/class/net/{}/ifindex", iface)).ok()?;
+        ["leases", "links"].iter().find_map(|dir| {
+            let path = Path::new("/run/systemd/netif").join(dir).join(index.trim());
+            parse_key_value_lease(&fs::read_to_string(path).ok()?)
+        })
+    }
+
+    /// dhclient lease files, and NetworkManager's copies of the leases of its
+    /// internal DHCP client, for `iface`.
+    fn lease_file_captive_portal(&self, iface: &str) -> Option<String> {
+        let mut files: Vec<(std::time::SystemTime, PathBuf)> = DHCLIENT_LEASE_DIRS
+            .iter()
+            .filter_map(|dir| fs::read_dir(dir).ok())
+            .flatten()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| is_lease_file_for(name, iface))
+            })
+            .filter_map(|path| Some((fs::metadata(&path).ok()?.modified().ok()?, path)))
+            .collect();
+        // the newest lease is the one the interface is using
+        files.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+        files.iter().find_map(|(_, path)| {
+            let content = fs::read_to_string(path).ok()?;
+            parse_dhclient_lease(&content).or_else(|| parse_key_value_lease(&content))
+        })
     }
 }
 
+#[async_trait]
 impl NetworkManager for LinuxNetworkManager {
-    fn is_adapater_on(&self) -> Result<bool> {
-        info!("checking Wi-Fi adapter state");
-        let (stdout, stderr, exit_code) = match self.get_interface_details() {
-            Ok(t) => t,
+    async fn is_adapater_on(&self) -> Result<bool> {
+        info!("checking network adapter state");
+        let devices = match self.bus().await {
+            Some(bus) => self.devices(bus).await,
+            None => Err(zbus::Error::Failure("no system bus".to_string())),
+        };
+        let devices = match devices {
+            Ok(devices) => devices,
             Err(e) => {
-                error!("failed to run netsh: {}", e);
-                return Ok(false);
+                warn!(
+                    "can't ask NetworkManager for devices ({}), looking for a default route",
+                    e
+                );
+                return Ok(self.kernel_link_info().is_some());
             }
         };
-        if exit_code != 0 {
-            warn!(
-                "nmcli returned non-zero exit code: {}. stderr_len={}",
-                exit_code,
-                stderr.len()
-            );
+        if devices.is_empty() {
+            info!("NetworkManager knows no Wi-Fi or Ethernet devices");
         }
-        match self.parse_interfaces(&stdout) {
-            Some(interfaces) => {
-                for (name, state) in interfaces.iter() {
-                    info!("found interface: '{}'", name);
-                    if state.to_lowercase().contains("connected") {
-                        info!("interface reports state: '{}', treating as ON", state);
-                        return Ok(true);
-                    } else {
-                        info!("interface reports state: '{}', treating as OFF", state);
-                    }
-                }
-                Ok(false)
+        for (device, state) in devices {
+            let name = device.interface().await.unwrap_or_default();
+            info!("interface '{}' is {}", name, nm::device_state_name(state));
+            if state == nm::DEVICE_STATE_ACTIVATED {
+                return Ok(true);
             }
-            None => {
-                info!("no interface 'Name' parsed — continuing to parse state");
-                warn!(
-                    "couldn't parse interface 'State' from netsh output; dumping snippet for diagnosis"
+        }
+        Ok(false)
+    }
+
+    async fn internet_available(&self, time_out_seconds: u64) -> bool {
+        match self.connectivity(time_out_seconds).await {
+            Some(Connectivity::Full) => {
+                info!("Internet is available, NetworkManager reports full connectivity");
+                true
+            }
+            Some(Connectivity::Portal) => {
+                info!("NetworkManager reports a captive portal");
+                false
+            }
+            Some(state) => {
+                error!(
+                    "Internet availability check failed: NetworkManager reports {:?} connectivity",
+                    state
                 );
-                let snippet: String = stdout.lines().take(20).collect::<Vec<&str>>().join("\n");
-                debug!("netsh stdout snippet:\n{}", snippet);
-                Ok(false)
+                false
+            }
+            None => platform::reach_internet(time_out_seconds).await,
+        }
+    }
+
+    async fn link_info(&self) -> Option<LinkInfo> {
+        let from_nm = match self.bus().await {
+            Some(bus) => self.nm_link_info(bus).await.unwrap_or_else(|e| {
+                warn!("can't read the link from NetworkManager: {}", e);
+                None
+            }),
+            None => None,
+        };
+        let mut link = from_nm.or_else(|| self.kernel_link_info())?;
+        if let Some(IpAddr::V4(gateway)) = link.gateway {
+            link.gateway_mac = fs::read_to_string("/proc/net/arp")
+                .ok()
+                .and_then(|arp| parse_arp_entry(&arp, gateway));
+        }
+        Some(link)
+    }
+
+    async fn captive_portal_uri(&self) -> Option<String> {
+        let from_nm = match self.bus().await {
+            Some(bus) => self.nm_captive_portals(bus).await.unwrap_or_else(|e| {
+                warn!("can't read DHCP options from NetworkManager: {}", e);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+        let interfaces = if from_nm.is_empty() {
+            self.kernel_link_info()
+                .map(|link| (link.interface, None))
+                .into_iter()
+                .collect()
+        } else {
+            from_nm
+        };
+        for (iface, advertised) in interfaces {
+            let found = advertised.or_else(|| {
+                self.networkd_captive_portal(&iface)
+                    .or_else(|| self.lease_file_captive_portal(&iface))
+            });
+            match found {
+                Some(uri) if uri == CAPPORT_UNRESTRICTED => {
+                    debug!("{} advertises no captive portal", iface);
+                }
+                Some(uri) => {
+                    info!("{} advertises captive portal API {}", iface, uri);
+                    return Some(uri);
+                }
+                None => trace!("no captive portal API advertised on {}", iface),
             }
         }
+        None
+    }
+}
+
+fn is_captive_portal_option(name: &str) -> bool {
+    let name = name.trim().to_ascii_lowercase().replace('-', "_");
+    // option 114 was "default-url" before RFC 7710 reassigned it
+    matches!(
+        name.as_str(),
+        "captive_portal" | "default_url" | "dhcp6_captive_portal"
+    )
+}
+
+/// Whether `name` is a lease file of `iface`. dhclient and NetworkManager
+/// end the name with the interface, as in `dhclient.wlan0.leases` or
+/// `internal-<uuid>-wlan0.lease`, so `wlan0` doesn't match `wlan01`.
+fn is_lease_file_for(name: &str, iface: &str) -> bool {
+    let Some(stem) = name
+        .strip_suffix(".lease")
+        .or_else(|| name.strip_suffix(".leases"))
+    else {
+        return false;
+    };
+    stem.strip_suffix(iface)
+        .is_some_and(|prefix| prefix.ends_with(['-', '.']))
+}
+
+/// The last option 114 in a dhclient lease file, which holds every lease the
+/// interface had with the current one last.
+fn parse_dhclient_lease(content: &str) -> Option<String> {
+    content
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("option "))
+        .filter_map(|option| option.split_once(char::is_whitespace))
+        .filter(|(name, _)| is_captive_portal_option(name))
+        .map(|(_, value)| {
+            value
+                .trim()
+                .trim_end_matches(';')
+                .trim_matches('"')
+                .to_string()
+        })
+        .next_back()
+}
+
+/// `CAPTIVE_PORTAL=...` as written by systemd-networkd and NetworkManager's
+/// internal DHCP client.
+fn parse_key_value_lease(content: &str) -> Option<String> {
+    content
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(key, _)| key.trim() == "CAPTIVE_PORTAL")
+        .map(|(_, value)| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// The interface and gateway of the default route in `/proc/net/route`,
+/// which prints addresses as hex in host byte order. Point-to-point links
+/// have no gateway.
+fn parse_default_route(routes: &str) -> Option<(String, Option<Ipv4Addr>)> {
+    routes.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [iface, "00000000", gateway, ..] => {
+                let gateway = u32::from_str_radix(gateway, 16).ok()?;
+                let gateway = Ipv4Addr::from(gateway.to_ne_bytes());
+                Some((
+                    iface.to_string(),
+                    Some(gateway).filter(|ip| !ip.is_unspecified()),
+                ))
+            }
+            _ => None,
+        }
+    })
+}
+
+/// Addresses as NetworkManager's `AddressData` and `NameserverData` hold
+/// them, skipping any that don't parse.
+fn parse_addresses(values: Vec<String>) -> Vec<IpAddr> {
+    values
+        .iter()
+        .filter_map(|value| value.parse().ok())
+        .collect()
+}
+
+/// The MAC address `/proc/net/arp` has resolved for `ip`.
+fn parse_arp_entry(arp: &str, ip: Ipv4Addr) -> Option<String> {
+    let ip = ip.to_string();
+    arp.lines().skip(1).find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [addr, _, flags, mac, ..] if *addr == ip && *flags != "0x0" => Some(mac.to_string()),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use zbus::zvariant::{OwnedObjectPath, OwnedValue, Str};
+
+    /// A dbus-daemon of our own, so the mock can take NetworkManager's name.
+    struct PrivateBus(Child);
+
+    impl PrivateBus {
+        fn start() -> (Self, String) {
+            let mut child = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address=1"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("can't start dbus-daemon");
+            let mut address = String::new();
+            BufReader::new(child.stdout.take().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            (Self(child), address.trim().to_string())
+        }
+    }
+
+    impl Drop for PrivateBus {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    fn path(path: &str) -> OwnedObjectPath {
+        OwnedObjectPath::try_from(path).unwrap()
+    }
+
+    fn address(address: &str) -> HashMap<String, OwnedValue> {
+        HashMap::from([("address".to_string(), OwnedValue::from(Str::from(address)))])
+    }
+
+    struct MockNetworkManager;
+
+    #[zbus::interface(name = "org.freedesktop.NetworkManager")]
+    impl MockNetworkManager {
+        fn check_connectivity(&self) -> u32 {
+            2
+        }
+        #[zbus(property)]
+        fn devices(&self) -> Vec<OwnedObjectPath> {
+            vec![path("/nm/Devices/1"), path("/nm/Devices/2")]
+        }
+        #[zbus(property)]
+        fn primary_connection(&self) -> OwnedObjectPath {
+            path("/nm/ActiveConnection/1")
+        }
+        #[zbus(property)]
+        fn connectivity(&self) -> u32 {
+            4
+        }
+        #[zbus(property)]
+        fn connectivity_check_enabled(&self) -> bool {
+            true
+        }
+    }
+
+    struct MockActiveConnection;
+
+    #[zbus::interface(name = "org.freedesktop.NetworkManager.Connection.Active")]
+    impl MockActiveConnection {
+        #[zbus(property)]
+        fn devices(&self) -> Vec<OwnedObjectPath> {
+            vec![path("/nm/Devices/2")]
+        }
+    }
+
+    struct MockDevice {
+        interface: &'static str,
+        device_type: u32,
+        state: u32,
+        configured: bool,
+    }
+
+    impl MockDevice {
+        fn config(&self, config: &str) -> OwnedObjectPath {
+            path(if self.configured { config } else { "/" })
+        }
+    }
+
+    #[zbus::interface(name = "org.freedesktop.NetworkManager.Device")]
+    impl MockDevice {
+        #[zbus(property)]
+        fn interface(&self) -> String {
+            self.interface.to_string()
+        }
+        #[zbus(property)]
+        fn device_type(&self) -> u32 {
+            self.device_type
+        }
+        #[zbus(property)]
+        fn state(&self) -> u32 {
+            self.state
+        }
+        #[zbus(property)]
+        fn ip4_config(&self) -> OwnedObjectPath {
+            self.config("/nm/IP4Config/1")
+        }
+        #[zbus(property)]
+        fn ip6_config(&self) -> OwnedObjectPath {
+            self.config("/nm/IP6Config/1")
+        }
+        #[zbus(property)]
+        fn dhcp4_config(&self) -> OwnedObjectPath {
+            self.config("/nm/DHCP4Config/1")
+        }
+        #[zbus(property)]
+        fn dhcp6_config(&self) -> OwnedObjectPath {
+            path("/")
+        }
+    }
+
+    struct MockWireless;
+
+    #[zbus::interface(name = "org.freedesktop.NetworkManager.Device.Wireless")]
+    impl MockWireless {
+        #[zbus(property)]
+        fn active_access_point(&self) -> OwnedObjectPath {
+            path("/nm/AccessPoint/1")
+        }
+    }
+
+    struct MockAccessPoint;
+
+    #[zbus::interface(name = "org.freedesktop.NetworkManager.AccessPoint")]
+    impl MockAccessPoint {
+        #[zbus(property)]
+        fn ssid(&self) -> Vec<u8> {
+            "Café: guests".as_bytes().to_vec()
+        }
+        #[zbus(property)]
+        fn hw_address(&self) -> String {
+            "AA:BB:CC:DD:EE:FF".to_string()
+        }
+        #[zbus(property)]
+        fn strength(&self) -> u8 {
+            72
+        }
+    }
+
+    struct MockIP4Config;
+
+    #[zbus::interface(name = "org.freedesktop.NetworkManager.IP4Config")]
+    impl MockIP4Config {
+        #[zbus(property)]
+        fn address_data(&self) -> Vec<HashMap<String, OwnedValue>> {
+            vec![address("10.0.0.23")]
+        }
+        #[zbus(property)]
+        fn gateway(&self) -> String {
+            "10.0.0.1".to_string()
+        }
+        #[zbus(property)]
+        fn nameserver_data(&self) -> Vec<HashMap<String, OwnedValue>> {
+            vec![address("10.0.0.1")]
+        }
+    }
+
+    struct MockIP6Config;
+
+    #[zbus::interface(name = "org.freedesktop.NetworkManager.IP6Config")]
+    impl MockIP6Config {
+        #[zbus(property)]
+        fn address_data(&self) -> Vec<HashMap<String, OwnedValue>> {
+            vec![address("fd00::23")]
+        }
+        #[zbus(property)]
+        fn gateway(&self) -> String {
+            String::new()
+        }
+        #[zbus(property)]
+        fn nameservers(&self) -> Vec<Vec<u8>> {
+            vec!["fd00::1".parse::<Ipv6Addr>().unwrap().octets().to_vec()]
+        }
+    }
+
+    struct MockDHCP4Config;
+
+    #[zbus::interface(name = "org.freedesktop.NetworkManager.DHCP4Config")]
+    impl MockDHCP4Config {
+        #[zbus(property)]
+        fn options(&self) -> HashMap<String, OwnedValue> {
+            HashMap::from([
+                (
+                    "ip_address".to_string(),
+                    OwnedValue::from(Str::from("10.0.0.23")),
+                ),
+                (
+                    "captive_portal".to_string(),
+                    OwnedValue::from(Str::from("https://portal.example/api")),
+                ),
+            ])
+        }
+    }
+
+    async fn serve(address: &str) -> zbus::Connection {
+        zbus::connection::Builder::address(address)
+            .unwrap()
+            .name("org.freedesktop.NetworkManager")
+            .unwrap()
+            .serve_at("/org/freedesktop/NetworkManager", MockNetworkManager)
+            .unwrap()
+            .serve_at("/nm/ActiveConnection/1", MockActiveConnection)
+            .unwrap()
+            .serve_at(
+                "/nm/Devices/1",
+                MockDevice {
+                    interface: "enp0s31f6",
+                    device_type: nm::DEVICE_TYPE_ETHERNET,
+                    state: 30,
+                    configured: false,
+                },
+            )
+            .unwrap()
+            .serve_at(
+                "/nm/Devices/2",
+                MockDevice {
+                    interface: "wlp2s0",
+                    device_type: nm::DEVICE_TYPE_WIFI,
+                    state: nm::DEVICE_STATE_ACTIVATED,
+                    configured: true,
+                },
+            )
+            .unwrap()
+            .serve_at("/nm/Devices/2", MockWireless)
+            .unwrap()
+            .serve_at("/nm/AccessPoint/1", MockAccessPoint)
+            .unwrap()
+            .serve_at("/nm/IP4Config/1", MockIP4Config)
+            .unwrap()
+            .serve_at("/nm/IP6Config/1", MockIP6Config)
+            .unwrap()
+            .serve_at("/nm/DHCP4Config/1", MockDHCP4Config)
+            .unwrap()
+            .build()
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn matches_lease_files_by_whole_interface_name() {
+        assert!(is_lease_file_for("dhclient.wlan0.leases", "wlan0"));
+        assert!(is_lease_file_for("dhclient6-wlan0.leases", "wlan0"));
+        assert!(is_lease_file_for(
+            "internal-8b1d6f3e-0c4a-4b1e-9f1a-2f5c8d7e6a10-wlan0.lease",
+            "wlan0"
+        ));
+        assert!(is_lease_file_for("dhclient.eth0.100.leases", "eth0.100"));
+        assert!(!is_lease_file_for("dhclient.wlan01.leases", "wlan0"));
+        assert!(!is_lease_file_for("dhclient.eth0.100.leases", "eth0"));
+        assert!(!is_lease_file_for("dhclient.wlan0.conf", "wlan0"));
+        assert!(!is_lease_file_for("wlan0.lease", "wlan0"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs dbus-daemon"]
+    async fn reads_network_manager_over_dbus() {
+        let (_bus, address) = PrivateBus::start();
+        let _service = serve(&address).await;
+        let client = zbus::connection::Builder::address(address.as_str())
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let nm = LinuxNetworkManager::with_connection(client);
+
+        assert!(nm.is_adapater_on().await.unwrap());
+        // the mock's own check finds a portal, the cached state says full
+        assert!(!nm.internet_available(1).await);
+        assert_eq!(
+            nm.captive_portal_uri().await.as_deref(),
+            Some("https://portal.example/api")
+        );
+
+        let link = nm.link_info().await.unwrap();
+        assert_eq!(link.interface, "wlp2s0");
+        assert_eq!(link.kind, LinkKind::Wifi);
+        assert_eq!(link.ssid.as_deref(), Some("Café: guests"));
+        assert_eq!(link.bssid.as_deref(), Some("AA:BB:CC:DD:EE:FF"));
+        assert_eq!(link.signal, Some(72));
+        assert_eq!(link.ipv4, vec![IpAddr::from([10, 0, 0, 23])]);
+        assert_eq!(link.ipv6, vec!["fd00::23".parse::<IpAddr>().unwrap()]);
+        assert_eq!(link.gateway, Some(IpAddr::from([10, 0, 0, 1])));
+        assert_eq!(
+            link.dns,
+            vec![
+                IpAddr::from([10, 0, 0, 1]),
+                "fd00::1".parse::<IpAddr>().unwrap()
+            ]
+        );
     }
 }
@@ -0,0 +1,80 @@
+//! Link, address and route changes as the kernel announces them over
+//! rtnetlink.
+
+use futures::StreamExt;
+use log::{debug, warn};
+use netlink_packet_core::NetlinkPayload;
+use netlink_packet_route::{IFF_LOWER_UP, IFF_RUNNING, IFF_UP, RT_TABLE_LOCAL, RtnlMessage};
+use netlink_sys::{AsyncSocket, SocketAddr};
+use rtnetlink::constants::{
+    RTMGRP_IPV4_IFADDR, RTMGRP_IPV4_ROUTE, RTMGRP_IPV6_IFADDR, RTMGRP_IPV6_ROUTE, RTMGRP_LINK,
+};
+use std::collections::HashMap;
+use std::io;
+
+/// Calls `on_change` whenever a link goes up or down, or an address or a
+/// route comes or goes, until the socket fails and `on_lost` is called.
+pub fn watch(
+    on_change: impl Fn() + Send + 'static,
+    on_lost: impl FnOnce() + Send + 'static,
+) -> io::Result<()> {
+    let (mut connection, _handle, mut messages) = rtnetlink::new_connection()?;
+    let groups = RTMGRP_LINK
+        | RTMGRP_IPV4_IFADDR
+        | RTMGRP_IPV6_IFADDR
+        | RTMGRP_IPV4_ROUTE
+        | RTMGRP_IPV6_ROUTE;
+    connection
+        .socket_mut()
+        .socket_mut()
+        .bind(&SocketAddr::new(0, groups))?;
+    tokio::spawn(connection);
+    tokio::spawn(async move {
+        let mut links = HashMap::new();
+        while let Some((message, _)) = messages.next().await {
+            if let NetlinkPayload::InnerMessage(message) = message.payload
+                && is_change(&mut links, &message)
+            {
+                on_change();
+            }
+        }
+        warn!("stopped receiving network changes from the kernel");
+        on_lost();
+    });
+    Ok(())
+}
+
+/// Whether `message` changes anything the state machine looks at. Wireless
+/// drivers announce scan results and the like as link updates, those only
+/// count when the link's up/running flags change. `links` remembers the
+/// flags of each link by index.
+fn is_change(links: &mut HashMap<u32, u32>, message: &RtnlMessage) -> bool {
+    match message {
+        RtnlMessage::NewLink(link) => {
+            let flags = link.header.flags & (IFF_UP | IFF_RUNNING | IFF_LOWER_UP);
+            let changed = links.insert(link.header.index, flags) != Some(flags);
+            if changed {
+                debug!("link {} now has flags {:#x}", link.header.index, flags);
+            }
+            changed
+        }
+        RtnlMessage::DelLink(link) => {
+            debug!("link {} is gone", link.header.index);
+            links.remove(&link.header.index);
+            true
+        }
+        RtnlMessage::NewAddress(address) | RtnlMessage::DelAddress(address) => {
+            debug!("address changed on link {}", address.header.index);
+            true
+        }
+        // every address comes with a route in the local table
+        RtnlMessage::NewRoute(route) | RtnlMessage::DelRoute(route) => {
+            let changed = route.header.table != RT_TABLE_LOCAL;
+            if changed {
+                debug!("route changed in table {}", route.header.table);
+            }
+            changed
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,230 @@
+//! The parts of NetworkManager's D-Bus API we read.
+//!
+//! See <https://networkmanager.dev/docs/api/latest/spec.html> for the
+//! properties and the values of the enums.
+
+use std::collections::HashMap;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+use zbus::{CacheProperties, Connection, ProxyDefault, proxy};
+
+pub const DEVICE_TYPE_ETHERNET: u32 = 1;
+pub const DEVICE_TYPE_WIFI: u32 = 2;
+
+pub const DEVICE_STATE_ACTIVATED: u32 = 100;
+
+/// NM_CONNECTIVITY, the result of NetworkManager's own connectivity check.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connectivity {
+    Unknown,
+    None,
+    Portal,
+    Limited,
+    Full,
+}
+
+impl From<u32> for Connectivity {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => Connectivity::None,
+            2 => Connectivity::Portal,
+            3 => Connectivity::Limited,
+            4 => Connectivity::Full,
+            _ => Connectivity::Unknown,
+        }
+    }
+}
+
+/// NM_DEVICE_STATE as it reads in logs.
+pub fn device_state_name(state: u32) -> &'static str {
+    match state {
+        10 => "unmanaged",
+        20 => "unavailable",
+        30 => "disconnected",
+        40 => "preparing",
+        50 => "configuring",
+        60 => "waiting for authentication",
+        70 => "requesting an address",
+        80 => "checking connectivity",
+        90 => "waiting for secondaries",
+        100 => "activated",
+        110 => "deactivating",
+        120 => "failed",
+        _ => "unknown",
+    }
+}
+
+/// NetworkManager uses `/` for object paths that aren't set.
+pub fn is_set(path: &OwnedObjectPath) -> bool {
+    path.as_str() != "/"
+}
+
+/// A proxy for the object at `path`. Properties are read when asked for,
+/// the proxies live for one check and there's nothing to cache.
+pub async fn at<P>(bus: &Connection, path: OwnedObjectPath) -> zbus::Result<P>
+where
+    P: From<zbus::Proxy<'static>> + ProxyDefault,
+{
+    zbus::proxy::Builder::new(bus)
+        .path(path)?
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await
+}
+
+pub async fn root(bus: &Connection) -> zbus::Result<NetworkManagerProxy<'static>> {
+    zbus::proxy::Builder::new(bus)
+        .cache_properties(CacheProperties::No)
+        .build()
+        .await
+}
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager",
+    default_service = "org.freedesktop.NetworkManager",
+    default_path = "/org/freedesktop/NetworkManager",
+    gen_blocking = false
+)]
+pub trait NetworkManager {
+    fn check_connectivity(&self) -> zbus::Result<u32>;
+
+    #[zbus(property)]
+    fn devices(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+
+    #[zbus(property)]
+    fn primary_connection(&self) -> zbus::Result<OwnedObjectPath>;
+
+    #[zbus(property)]
+    fn connectivity(&self) -> zbus::Result<u32>;
+
+    #[zbus(property)]
+    fn connectivity_check_enabled(&self) -> zbus::Result<bool>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager.Connection.Active",
+    default_service = "org.freedesktop.NetworkManager",
+    gen_blocking = false
+)]
+pub trait ActiveConnection {
+    #[zbus(property)]
+    fn devices(&self) -> zbus::Result<Vec<OwnedObjectPath>>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager.Device",
+    default_service = "org.freedesktop.NetworkManager",
+    gen_blocking = false
+)]
+pub trait Device {
+    #[zbus(property)]
+    fn interface(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn device_type(&self) -> zbus::Result<u32>;
+
+    #[zbus(property)]
+    fn state(&self) -> zbus::Result<u32>;
+
+    #[zbus(property)]
+    fn ip4_config(&self) -> zbus::Result<OwnedObjectPath>;
+
+    #[zbus(property)]
+    fn ip6_config(&self) -> zbus::Result<OwnedObjectPath>;
+
+    #[zbus(property)]
+    fn dhcp4_config(&self) -> zbus::Result<OwnedObjectPath>;
+
+    #[zbus(property)]
+    fn dhcp6_config(&self) -> zbus::Result<OwnedObjectPath>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager.Device.Wireless",
+    default_service = "org.freedesktop.NetworkManager",
+    gen_blocking = false
+)]
+pub trait Wireless {
+    #[zbus(property)]
+    fn active_access_point(&self) -> zbus::Result<OwnedObjectPath>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager.AccessPoint",
+    default_service = "org.freedesktop.NetworkManager",
+    gen_blocking = false
+)]
+pub trait AccessPoint {
+    #[zbus(property)]
+    fn ssid(&self) -> zbus::Result<Vec<u8>>;
+
+    #[zbus(property)]
+    fn hw_address(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn strength(&self) -> zbus::Result<u8>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager.IP4Config",
+    default_service = "org.freedesktop.NetworkManager",
+    gen_blocking = false
+)]
+pub trait IP4Config {
+    #[zbus(property)]
+    fn address_data(&self) -> zbus::Result<Vec<HashMap<String, OwnedValue>>>;
+
+    #[zbus(property)]
+    fn gateway(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn nameserver_data(&self) -> zbus::Result<Vec<HashMap<String, OwnedValue>>>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager.IP6Config",
+    default_service = "org.freedesktop.NetworkManager",
+    gen_blocking = false
+)]
+pub trait IP6Config {
+    #[zbus(property)]
+    fn address_data(&self) -> zbus::Result<Vec<HashMap<String, OwnedValue>>>;
+
+    #[zbus(property)]
+    fn gateway(&self) -> zbus::Result<String>;
+
+    #[zbus(property)]
+    fn nameservers(&self) -> zbus::Result<Vec<Vec<u8>>>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager.DHCP4Config",
+    default_service = "org.freedesktop.NetworkManager",
+    gen_blocking = false
+)]
+pub trait DHCP4Config {
+    #[zbus(property)]
+    fn options(&self) -> zbus::Result<HashMap<String, OwnedValue>>;
+}
+
+#[proxy(
+    interface = "org.freedesktop.NetworkManager.DHCP6Config",
+    default_service = "org.freedesktop.NetworkManager",
+    gen_blocking = false
+)]
+pub trait DHCP6Config {
+    #[zbus(property)]
+    fn options(&self) -> zbus::Result<HashMap<String, OwnedValue>>;
+}
+
+/// The string values of `key` in a list of dicts, like the `address` of
+/// each entry in `AddressData`.
+pub fn strings(dicts: &[HashMap<String, OwnedValue>], key: &str) -> Vec<String> {
+    dicts
+        .iter()
+        .filter_map(|dict| string(dict.get(key)?))
+        .collect()
+}
+
+pub fn string(value: &OwnedValue) -> Option<String> {
+    value.downcast_ref::<&str>().ok().map(str::to_string)
+}
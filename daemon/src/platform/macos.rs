@@ -1,7 +1,9 @@
-use crate::platform::NetworkManager;
+use crate::platform::{LinkInfo, LinkKind, NetworkManager};
 use anyhow::{Result, anyhow};
-use log::{error, info, warn};
-use std::process::Command;
+use async_trait::async_trait;
+use log::{debug, error, info, warn};
+use std::net::IpAddr;
+use tokio::process::Command;
 
 pub struct MacOSNetworkManager;
 
@@ -10,12 +12,13 @@ impl MacOSNetworkManager {
         Self
     }
 
-    fn _get_adapter_status(&self) -> Result<String> {
+    async fn _get_adapter_status(&self) -> Result<String> {
         info!("Checking for Wi-Fi adapter status...");
 
         let output = Command::new("networksetup")
             .arg("-listallhardwareports")
-            .output()?;
+            .output()
+            .await?;
 
         if !output.status.success() {
             error!("Failed to execute 'networksetup -listallhardwareports'");
@@ -48,18 +51,30 @@ impl MacOSNetworkManager {
         error!("No Wi-Fi device found in the 'networksetup' output.");
         Err(anyhow!("No Wi-Fi device found"))
     }
+
+    /// Output of a command that succeeded.
+    async fn run(&self, program: &str, args: &[&str]) -> Option<String> {
+        let output = Command::new(program).args(args).output().await.ok()?;
+        if !output.status.success() {
+            debug!("{} {:?} exited with {}", program, args, output.status);
+            return None;
+        }
+        Some(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
 }
 
+#[async_trait]
 impl NetworkManager for MacOSNetworkManager {
-    fn is_adapater_on(&self) -> Result<bool> {
+    async fn is_adapater_on(&self) -> Result<bool> {
         info!("Checking if Wi-Fi adapter is on...");
-        match self._get_adapter_status() {
+        match self._get_adapter_status().await {
             Ok(dev) => {
                 info!("Checking the power status of the Wi-Fi device: {}", dev);
                 let output = Command::new("networksetup")
                     .arg("-getairportpower")
                     .arg(&dev)
-                    .output()?;
+                    .output()
+                    .await?;
 
                 if !output.status.success() {
                     error!("Failed to get airport power status for device: {}", dev);
@@ -83,4 +98,78 @@ impl NetworkManager for MacOSNetworkManager {
             }
         }
     }
+
+    /// macOS no longer has a supported command line tool that reports the
+    /// BSSID or signal, so those are left out.
+    async fn link_info(&self) -> Option<LinkInfo> {
+        // "  interface: en0" and "    gateway: 192.168.1.1"
+        let route = self.run("route", &["-n", "get", "default"]).await?;
+        let field = |name: &str| {
+            route
+                .lines()
+                .find_map(|line| line.trim().strip_prefix(name))
+                .map(str::trim)
+        };
+        let mut link = LinkInfo {
+            interface: field("interface:")?.to_string(),
+            gateway: field("gateway:").and_then(|gateway| gateway.parse().ok()),
+            ..Default::default()
+        };
+        if self
+            ._get_adapter_status()
+            .await
+            .is_ok_and(|dev| dev == link.interface)
+        {
+            link.kind = LinkKind::Wifi;
+            // "Current Wi-Fi Network: Campus"
+            link.ssid = self
+                .run("networksetup", &["-getairportnetwork", &link.interface])
+                .await
+                .and_then(|out| {
+                    out.split_once("Network:")
+                        .map(|(_, ssid)| ssid.trim().to_string())
+                });
+        } else if link.interface.starts_with("en") {
+            link.kind = LinkKind::Ethernet;
+        }
+        if let Some(ifconfig) = self.run("ifconfig", &[&link.interface]).await {
+            for line in ifconfig.lines() {
+                let mut words = line.split_whitespace();
+                let (Some(family), Some(addr)) = (words.next(), words.next()) else {
+                    continue;
+                };
+                // link-local addresses carry their scope, "fe80::1%en0"
+                let addr = addr.split('%').next().unwrap_or_default().parse::<IpAddr>();
+                match (family, addr) {
+                    ("inet", Ok(addr)) => link.ipv4.push(addr),
+                    ("inet6", Ok(addr)) => link.ipv6.push(addr),
+                    _ => {}
+                }
+            }
+        }
+        // "  nameserver[0] : 192.168.1.1", repeated for every resolver
+        if let Some(dns) = self.run("scutil", &["--dns"]).await {
+            for server in dns
+                .lines()
+                .filter(|line| line.trim_start().starts_with("nameserver["))
+                .filter_map(|line| line.split_once(':')?.1.trim().parse::<IpAddr>().ok())
+            {
+                if !link.dns.contains(&server) {
+                    link.dns.push(server);
+                }
+            }
+        }
+        // "? (192.168.1.1) at aa:bb:cc:dd:ee:ff on en0 ifscope [ethernet]"
+        if let Some(gateway) = link.gateway {
+            link.gateway_mac = self
+                .run("arp", &["-n", &gateway.to_string()])
+                .await
+                .and_then(|arp| {
+                    let mut words = arp.split_whitespace().skip_while(|word| *word != "at");
+                    words.nth(1).map(str::to_string)
+                })
+                .filter(|mac| mac.contains(':'));
+        }
+        Some(link)
+    }
 }
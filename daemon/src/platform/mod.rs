@@ -1,24 +1,103 @@
+use async_trait::async_trait;
 use log::{error, info};
-use std::net::TcpStream;
-use tokio::time::Duration;
+use serde::Serialize;
+use std::fmt;
+use std::net::IpAddr;
+use tokio::net::TcpStream;
+use tokio::time::{Duration, timeout};
 
-pub trait NetworkManager: Send + Sync {
-    // fn current_ssid(&self) -> anyhow::Result<Option<String>>;
-    fn is_adapater_on(&self) -> anyhow::Result<bool>;
-    fn internet_available(&self, time_out_seconds: u64) -> bool {
-        let timeout = Duration::from_secs(time_out_seconds);
-        let result = TcpStream::connect_timeout(&"8.8.8.8:53".parse().unwrap(), timeout);
-        match result {
-            Ok(_) => {
-                info!("Internet is available, successfully connected to 8.8.8.8:53");
-                true
-            }
-            Err(e) => {
-                error!("Internet availability check failed: {}", e);
-                false
-            }
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkKind {
+    Wifi,
+    Ethernet,
+    #[default]
+    Other,
+}
+
+/// The link we reach the network through.
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct LinkInfo {
+    pub interface: String,
+    pub kind: LinkKind,
+    pub ssid: Option<String>,
+    pub bssid: Option<String>,
+    /// Wi-Fi signal quality in percent.
+    pub signal: Option<u8>,
+    pub ipv4: Vec<IpAddr>,
+    pub ipv6: Vec<IpAddr>,
+    pub gateway: Option<IpAddr>,
+    pub gateway_mac: Option<String>,
+    pub dns: Vec<IpAddr>,
+}
+
+impl fmt::Display for LinkInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.ssid {
+            Some(ssid) => write!(f, "'{}' on {}", ssid, self.interface)?,
+            None => write!(f, "{}", self.interface)?,
+        }
+        if let Some(gateway) = self.gateway {
+            write!(f, " via {}", gateway)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether a TCP connection to a public DNS server goes through within
+/// `time_out_seconds`.
+pub async fn reach_internet(time_out_seconds: u64) -> bool {
+    let limit = Duration::from_secs(time_out_seconds);
+    let result = timeout(limit, TcpStream::connect("8.8.8.8:53"))
+        .await
+        .unwrap_or_else(|elapsed| Err(elapsed.into()));
+    match result {
+        Ok(_) => {
+            info!("Internet is available, successfully connected to 8.8.8.8:53");
+            true
         }
+        Err(e) => {
+            error!("Internet availability check failed: {}", e);
+            false
+        }
+    }
+}
+
+/// Calls `on_change` whenever the network changes, where the platform can
+/// tell. `false` when it can't and polling is all there is, as it is again
+/// once `on_lost` is called.
+pub fn watch_changes(
+    on_change: impl Fn() + Send + 'static,
+    on_lost: impl FnOnce() + Send + 'static,
+) -> bool {
+    #[cfg(target_os = "linux")]
+    return match linux::netlink::watch(on_change, on_lost) {
+        Ok(()) => true,
+        Err(e) => {
+            log::warn!("can't watch for network changes: {}", e);
+            false
+        }
+    };
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (on_change, on_lost);
+        false
+    }
+}
+
+#[async_trait]
+pub trait NetworkManager: Send + Sync {
+    async fn is_adapater_on(&self) -> anyhow::Result<bool>;
+    async fn internet_available(&self, time_out_seconds: u64) -> bool {
+        reach_internet(time_out_seconds).await
+    }
+    /// The RFC 8908 Captive Portal API URI the network advertised in DHCP
+    /// option 114 or RA option 37 (RFC 8910), if the platform can tell.
+    async fn captive_portal_uri(&self) -> Option<String> {
+        None
     }
+    /// The link carrying the default route, `None` when there is none.
+    async fn link_info(&self) -> Option<LinkInfo>;
 }
 
 #[cfg(target_os = "macos")]
@@ -1,8 +1,10 @@
-use crate::platform::NetworkManager;
+use crate::platform::{LinkInfo, LinkKind, NetworkManager};
 use anyhow::{Result, anyhow};
+use async_trait::async_trait;
 use log::{debug, error, info, trace, warn};
-use std::process::Command;
+use std::net::IpAddr;
 use std::time::Instant;
+use tokio::process::Command;
 
 pub struct WindowsNetworkManager;
 
@@ -11,12 +13,13 @@ impl WindowsNetworkManager {
         Self
     }
 
-    fn get_interface_details(&self) -> Result<(String, String, i32)> {
+    async fn get_interface_details(&self) -> Result<(String, String, i32)> {
         let start = Instant::now();
         debug!("running: netsh wlan show interfaces");
         let output = Command::new("netsh")
-            .args(&["interface", "show", "interface"])
-            .output()?;
+            .args(["interface", "show", "interface"])
+            .output()
+            .await?;
         let duration = start.elapsed();
         let code = output.status.code().unwrap_or(-1);
         let stdout = String::from_utf8_lossy(&output.stdout).to_string();
@@ -35,6 +38,27 @@ impl WindowsNetworkManager {
         Ok((stdout, stderr, code))
     }
 
+    /// `netsh` output of a command that succeeded.
+    async fn netsh(&self, args: &[&str]) -> Option<String> {
+        let output = Command::new("netsh").args(args).output().await.ok()?;
+        if !output.status.success() {
+            debug!("netsh {:?} exited with {}", args, output.status);
+            return None;
+        }
+        Some(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// The first interface `netsh interface show interface` lists as
+    /// connected, below the "Admin State  State  Type  Interface Name" header.
+    async fn connected_interface(&self) -> Option<String> {
+        let out = self.netsh(&["interface", "show", "interface"]).await?;
+        out.lines().skip(3).find_map(|line| {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            (words.len() > 3 && words[1].eq_ignore_ascii_case("connected"))
+                .then(|| words[3..].join(" "))
+        })
+    }
+
     fn parse_interfaces(&self, netsh_out: &str) -> Option<Vec<(String, String)>> {
         let mut interface_states: Vec<(String, String)> = Vec::new();
         for line in netsh_out.lines().skip(2) {
@@ -53,10 +77,11 @@ impl WindowsNetworkManager {
     }
 }
 
+#[async_trait]
 impl NetworkManager for WindowsNetworkManager {
-    fn is_adapater_on(&self) -> Result<bool> {
+    async fn is_adapater_on(&self) -> Result<bool> {
         info!("checking Windows Wi-Fi adapter state");
-        let (stdout, stderr, exit_code) = match self.get_interface_details() {
+        let (stdout, stderr, exit_code) = match self.get_interface_details().await {
             Ok(t) => t,
             Err(e) => {
                 error!("failed to run netsh: {}", e);
@@ -94,4 +119,94 @@ impl NetworkManager for WindowsNetworkManager {
             }
         }
     }
+
+    async fn link_info(&self) -> Option<LinkInfo> {
+        let mut link = LinkInfo::default();
+        let wlan = self
+            .netsh(&["wlan", "show", "interfaces"])
+            .await
+            .map(|out| key_values(&out))
+            .unwrap_or_default();
+        let wlan_field = |name: &str| {
+            wlan.iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.clone())
+        };
+        if wlan_field("State").is_some_and(|state| state.eq_ignore_ascii_case("connected")) {
+            link.interface = wlan_field("Name")?;
+            link.kind = LinkKind::Wifi;
+            link.ssid = wlan_field("SSID");
+            link.bssid = wlan_field("BSSID");
+            link.signal =
+                wlan_field("Signal").and_then(|signal| signal.trim_end_matches('%').parse().ok());
+        } else {
+            link.interface = self.connected_interface().await?;
+            if link.interface.to_ascii_lowercase().contains("ethernet") {
+                link.kind = LinkKind::Ethernet;
+            }
+        }
+        let name = format!("name={}", link.interface);
+        let ipv4 = self
+            .netsh(&["interface", "ipv4", "show", "config", &name])
+            .await
+            .map(|out| key_values(&out))
+            .unwrap_or_default();
+        for (key, value) in ipv4 {
+            let Ok(addr) = value.parse::<IpAddr>() else {
+                continue;
+            };
+            match key.as_str() {
+                "IP Address" => link.ipv4.push(addr),
+                "Default Gateway" => link.gateway = link.gateway.or(Some(addr)),
+                key if key.contains("DNS") => link.dns.push(addr),
+                _ => {}
+            }
+        }
+        // "Address fe80::1%12 Parameters"
+        let interface = format!("interface={}", link.interface);
+        if let Some(out) = self
+            .netsh(&["interface", "ipv6", "show", "addresses", &interface])
+            .await
+        {
+            link.ipv6 = out
+                .lines()
+                .filter_map(|line| line.trim().strip_prefix("Address "))
+                .filter_map(|rest| rest.split(['%', ' ']).next()?.parse().ok())
+                .collect();
+        }
+        // "  192.168.1.1           aa-bb-cc-dd-ee-ff     dynamic"
+        if let Some(gateway) = link.gateway {
+            let gateway = gateway.to_string();
+            let arp = Command::new("arp").args(["-a", &gateway]).output().await;
+            if let Ok(arp) = arp {
+                link.gateway_mac = String::from_utf8_lossy(&arp.stdout)
+                    .lines()
+                    .find_map(|line| {
+                        let words: Vec<&str> = line.split_whitespace().collect();
+                        (words.len() > 1 && words[0] == gateway).then(|| words[1].to_string())
+                    });
+            }
+        }
+        Some(link)
+    }
+}
+
+/// `Key : value` lines, the way `netsh` prints them. Lines without a key
+/// continue the value of the line before, like the second DNS server.
+fn key_values(out: &str) -> Vec<(String, String)> {
+    let mut pairs: Vec<(String, String)> = Vec::new();
+    for line in out.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        match line.split_once(':') {
+            // a bare IPv6 address has colons but no key before them
+            Some((key, value)) if line.parse::<IpAddr>().is_err() => {
+                pairs.push((key.trim().to_string(), value.trim().to_string()));
+            }
+            _ => {
+                if let Some((key, _)) = pairs.last() {
+                    pairs.push((key.clone(), line.to_string()));
+                }
+            }
+        }
+    }
+    pairs
 }